@@ -0,0 +1,99 @@
+use std::{fmt, fs::File, io::{self, Cursor, Read}, iter, sync::mpsc::{self, Receiver, Sender}};
+
+/// A body which is not held in memory as a string. It is
+/// written to the connection after the head of the response.
+///
+/// Bodies which do not know their length up front are sent with
+/// chunked encoding to HTTP/1.1 clients, and by closing the
+/// connection after the body to HTTP/1.0 clients.
+pub enum Body {
+  Bytes(Vec<u8>),
+  /// A file which is sent from its current position,
+  /// with the number of bytes to send.
+  File(File, u64),
+  /// A reader which is read until it ends, or until
+  /// the length if it is known.
+  Reader(Box<Read + Send>, Option<u64>),
+  /// Chunks which are sent as they are produced.
+  Chunks(Box<Iterator<Item = Vec<u8>> + Send>),
+  /// Chunks sent by another thread, until every
+  /// sender has been dropped.
+  Channel(Receiver<Vec<u8>>),
+}
+
+impl Body {
+  /// A body which sends all of the file.
+  pub fn file(file: File) -> io::Result<Body> {
+    let length = file.metadata()?.len();
+    Ok(Body::File(file, length))
+  }
+
+  /// A body which sends what the reader gives until it ends.
+  pub fn reader<R: Read + Send + 'static>(reader: R) -> Body {
+    Body::Reader(Box::new(reader), None)
+  }
+
+  /// A body which sends every chunk the iterator gives.
+  pub fn chunks<I>(chunks: I) -> Body
+  where
+    I: Iterator<Item = Vec<u8>> + Send + 'static,
+  {
+    Body::Chunks(Box::new(chunks))
+  }
+
+  /// A body which sends the chunks which are sent through
+  /// the sender, which can be moved to another thread.
+  pub fn channel() -> (Sender<Vec<u8>>, Body) {
+    let (sender, receiver) = mpsc::channel();
+    (sender, Body::Channel(receiver))
+  }
+
+  /// The body with the bytes sent after it, without reading
+  /// the body into memory.
+  pub fn followed_by(self, bytes: Vec<u8>) -> Body {
+    let added = bytes.len() as u64;
+    match self {
+      Body::Bytes(mut body) => {
+        body.extend(bytes);
+        Body::Bytes(body)
+      }
+      Body::File(file, length) => Body::Reader(
+        Box::new(file.take(length).chain(Cursor::new(bytes))),
+        Some(length + added),
+      ),
+      Body::Reader(reader, Some(length)) => Body::Reader(
+        Box::new(reader.take(length).chain(Cursor::new(bytes))),
+        Some(length + added),
+      ),
+      Body::Reader(reader, None) => Body::Reader(Box::new(reader.chain(Cursor::new(bytes))), None),
+      Body::Chunks(chunks) => Body::Chunks(Box::new(chunks.chain(iter::once(bytes)))),
+      Body::Channel(receiver) => {
+        Body::Chunks(Box::new(receiver.into_iter().chain(iter::once(bytes))))
+      }
+    }
+  }
+
+  /// The number of bytes in the body, if it is known
+  /// before the body is sent.
+  pub fn length(&self) -> Option<u64> {
+    match *self {
+      Body::Bytes(ref bytes) => Some(bytes.len() as u64),
+      Body::File(_, length) => Some(length),
+      Body::Reader(_, length) => length,
+      Body::Chunks(_) | Body::Channel(_) => None,
+    }
+  }
+}
+
+impl fmt::Debug for Body {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Body::Bytes(ref bytes) => write!(fmt, "Body::Bytes({} bytes)", bytes.len()),
+      Body::File(_, length) => write!(fmt, "Body::File({} bytes)", length),
+      Body::Reader(_, Some(length)) => write!(fmt, "Body::Reader({} bytes)", length),
+      Body::Reader(_, None) => write!(fmt, "Body::Reader"),
+      Body::Chunks(_) => write!(fmt, "Body::Chunks"),
+      Body::Channel(_) => write!(fmt, "Body::Channel"),
+    }
+  }
+}
@@ -32,10 +32,33 @@ impl Contentable for Content {
     mem::replace(&mut self.body, new_body.into())
   }
   fn has_header(&self, name: &str) -> Option<&str> {
-    self.headers.get(name).map(|s| s.as_str())
+    self
+      .headers
+      .get(name)
+      .or_else(|| {
+        // Header names are case-insensitive (RFC 7230, 3.2)
+        self
+          .headers
+          .iter()
+          .find(|&(k, _)| k.eq_ignore_ascii_case(name))
+          .map(|(_, v)| v)
+      })
+      .map(|s| s.as_str())
   }
   fn add_header<S: Into<String>>(&mut self, name: S, value: S) -> Option<String> {
-    self.headers.insert(name.into(), value.into())
+    let name = name.into();
+    // The same header in another case is replaced, as it is the same header
+    let previous = self.remove_header(&name);
+    self.headers.insert(name, value.into());
+    previous
+  }
+  fn remove_header(&mut self, name: &str) -> Option<String> {
+    let key = self
+      .headers
+      .keys()
+      .find(|k| k.eq_ignore_ascii_case(name))
+      .cloned();
+    key.and_then(|key| self.headers.remove(&key))
   }
 }
 
@@ -45,15 +68,24 @@ impl Default for Content {
   }
 }
 
-impl Display for Content {
-  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+impl Content {
+  /// Writes the headers and the empty line which ends them,
+  /// for messages which are sent without their body.
+  pub fn fmt_headers(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
     let header_str: String = self
       .headers
       .iter()
       .map(|(k, v)| format!("{}: {}", k.to_string(), v.to_string()))
       .fold(String::new(), |acc, l| format!("{}\r\n{}", l, acc));
 
-    write!(fmt, "{}\r\n{}", header_str, self.body)
+    write!(fmt, "{}\r\n", header_str)
+  }
+}
+
+impl Display for Content {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+    self.fmt_headers(fmt)?;
+    write!(fmt, "{}", self.body)
   }
 }
 
@@ -107,8 +139,11 @@ pub trait Contentable {
   /// Checks to see if header exists and returns value of said header
   fn has_header(&self, name: &str) -> Option<&str>;
   /// Adds a header to the message. Will return "Some()" with the value of
-  /// the previously defined header if overwriting.
+  /// the previously defined header if overwriting, in any case.
   fn add_header<S: Into<String>>(&mut self, name: S, value: S) -> Option<String>;
+  /// Removes a header from the message, in any case, and
+  /// returns its value if it was defined.
+  fn remove_header(&mut self, name: &str) -> Option<String>;
 }
 
 #[cfg(test)]
@@ -187,6 +222,27 @@ mod tests {
     );
   }
 
+  #[test]
+  fn headers_are_case_insensitive() {
+    let mut cont = Content::new("");
+    cont.add_header("Content-Length", "0");
+
+    assert_eq!(
+      Some("0"),
+      cont.has_header("content-length"),
+      "Didn't find header with different case"
+    );
+
+    assert_eq!(
+      Some("0".to_string()),
+      cont.add_header("content-length", "5")
+    );
+    assert_eq!(1, cont.headers.len());
+    assert_eq!(Some("5"), cont.has_header("Content-Length"));
+    assert_eq!(Some("5".to_string()), cont.remove_header("CONTENT-LENGTH"));
+    assert_eq!(None, cont.has_header("Content-Length"));
+  }
+
   #[test]
   fn replace_body() {
     let mut cont = Content::new("{\"username\": \"johnny\"}");
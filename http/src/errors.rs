@@ -1,7 +1,13 @@
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum ParseErrorKind {
+/// The reason parsing failed. Lets a server choose
+/// a fitting status for a request it cannot parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
   Invalid,
   Empty,
+  /// The message uses a http version which is not supported.
+  UnsupportedVersion,
+  /// The request method is well-formed, but not known.
+  UnknownMethod,
 }
 
 macro_rules! parse_from_string_error {
@@ -23,6 +29,22 @@ macro_rules! parse_from_string_error {
           kind: ParseErrorKind::Empty
         }
       }
+
+      pub fn unsupported_version() -> Self {
+        $error_name {
+          kind: ParseErrorKind::UnsupportedVersion
+        }
+      }
+
+      pub fn unknown_method() -> Self {
+        $error_name {
+          kind: ParseErrorKind::UnknownMethod
+        }
+      }
+
+      pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+      }
     }
 
     impl ::std::fmt::Display for $error_name {
@@ -38,6 +60,8 @@ macro_rules! parse_from_string_error {
         match self.kind {
           Invalid => concat!("invalid ", stringify!($type_name), " literal"),
           Empty => concat!("cannot parse ", stringify!($type_name), " from empty string"),
+          UnsupportedVersion => concat!("unsupported http version in ", stringify!($type_name)),
+          UnknownMethod => concat!("unknown method in ", stringify!($type_name)),
         }
       }
     }
@@ -51,10 +75,8 @@ macro_rules! parse_from_string_error {
     $(
       impl From<$child_parse_error_name> for $error_name {
         fn from(err: $child_parse_error_name) -> Self {
-          use self::ParseErrorKind::*;
-          match err.kind {
-            Invalid => $error_name::invalid(),
-            Empty => $error_name::empty()
+          $error_name {
+            kind: err.kind
           }
         }
       }
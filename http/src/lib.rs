@@ -4,6 +4,7 @@
 //! across a Tcp-connection. The crate was mainly
 //! created to be a learning experience.
 
+pub mod body;
 pub mod errors;
 pub mod content;
 pub mod request;
@@ -11,7 +12,10 @@ pub mod response;
 
 pub const HTTP_VERSION: &str = "HTTP/1.1";
 
+pub use self::body::Body;
+pub use self::errors::ParseErrorKind;
 pub use self::errors::ParseHttpError;
+pub use self::request::HttpVersion;
 pub use self::request::Request;
 pub use self::request::RequestMethod;
 pub use self::response::Response;
@@ -1,4 +1,6 @@
-use std::{convert::TryFrom, fmt::{self, Display}, str::FromStr};
+use std::{collections::HashMap, convert::TryFrom, fmt::{self, Display}, ptr, str::FromStr};
+use std::io::{Cursor, Read};
+use std::sync::{Mutex, MutexGuard};
 
 use super::content::{Content, Contentable};
 use super::HTTP_VERSION;
@@ -12,7 +14,44 @@ pub use super::errors::{ParseRequestError, ParseRequestMethodError};
 pub struct Request {
   method: RequestMethod,
   uri: String,
+  version: HttpVersion,
   content: Content,
+  params: HashMap<String, String>,
+  reader: BodyReader,
+}
+
+/// The body of a request which is read as it is needed,
+/// rather than held in the content of the request.
+struct BodyReader(Mutex<Option<Box<Read + Send>>>);
+
+impl BodyReader {
+  fn empty() -> BodyReader {
+    BodyReader(Mutex::new(None))
+  }
+
+  fn lock<'a>(&'a self) -> MutexGuard<'a, Option<Box<Read + Send>>> {
+    match self.0.lock() {
+      Ok(reader) => reader,
+      Err(poisoned) => poisoned.into_inner(),
+    }
+  }
+}
+
+impl fmt::Debug for BodyReader {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+    match *self.lock() {
+      Some(_) => write!(fmt, "BodyReader"),
+      None => write!(fmt, "None"),
+    }
+  }
+}
+
+impl PartialEq for BodyReader {
+  /// Bodies which are read as they are needed cannot be compared,
+  /// so a request with one is only equal to itself.
+  fn eq(&self, other: &BodyReader) -> bool {
+    ptr::eq(self, other) || (self.lock().is_none() && other.lock().is_none())
+  }
 }
 
 impl Request {
@@ -20,7 +59,10 @@ impl Request {
     Request {
       method,
       uri: uri.into(),
+      version: HttpVersion::Http11,
       content: Content::default(),
+      params: HashMap::new(),
+      reader: BodyReader::empty(),
     }
   }
 
@@ -30,9 +72,56 @@ impl Request {
   pub fn uri(&self) -> &str {
     &self.uri
   }
+  pub fn set_uri<S: Into<String>>(&mut self, uri: S) {
+    self.uri = uri.into();
+  }
+  pub fn version(&self) -> HttpVersion {
+    self.version
+  }
+
+  /// The uri without the query.
+  pub fn path(&self) -> &str {
+    match self.uri.find('?') {
+      Some(pos) => &self.uri[..pos],
+      None => &self.uri,
+    }
+  }
+  /// The part of the uri after `?`, if there is one.
+  pub fn query(&self) -> Option<&str> {
+    self.uri.find('?').map(|pos| &self.uri[pos + 1..])
+  }
+
+  /// A value captured from the path by the route
+  /// which matched the request.
+  pub fn param(&self, name: &str) -> Option<&str> {
+    self.params.get(name).map(|s| s.as_str())
+  }
+  pub fn set_param<S: Into<String>>(&mut self, name: S, value: S) -> Option<String> {
+    self.params.insert(name.into(), value.into())
+  }
+
+  /// Makes the body be read from the reader when it is needed,
+  /// such as from the connection the head was read from.
+  pub fn set_body_reader<R: Read + Send + 'static>(&mut self, reader: R) {
+    self.reader = BodyReader(Mutex::new(Some(Box::new(reader))));
+  }
+
+  /// Gives the body to be read incrementally. A body which is set
+  /// with `set_body_reader` can only be read once, so the reader is
+  /// empty when it is asked for again. Otherwise the reader reads
+  /// the body string.
+  pub fn body_reader(&self) -> Box<Read + Send> {
+    match self.reader.lock().take() {
+      Some(reader) => reader,
+      None => Box::new(Cursor::new(self.get_body().as_bytes().to_vec())),
+    }
+  }
 }
 
 impl Contentable for Request {
+  /// The body string. A body which is set with `set_body_reader`,
+  /// such as the body of a request read from a connection, is not
+  /// in it, and is read with `body_reader` instead.
   fn get_body(&self) -> &str {
     self.content.get_body()
   }
@@ -45,6 +134,9 @@ impl Contentable for Request {
   fn add_header<S: Into<String>>(&mut self, name: S, value: S) -> Option<String> {
     self.content.add_header(name, value)
   }
+  fn remove_header(&mut self, name: &str) -> Option<String> {
+    self.content.remove_header(name)
+  }
 }
 
 impl TryFrom<String> for Request {
@@ -67,19 +159,22 @@ impl TryFrom<String> for Request {
     if request_line.len() < 3 {
       return Err(ParseRequestError::invalid());
     }
-    let version = request_line[2];
-    if version != HTTP_VERSION {
-      return Err(ParseRequestError::invalid());
-    }
-
+    let version = HttpVersion::from_str(request_line[2])?;
     let method = RequestMethod::from_str(request_line[0])?;
     let uri = request_line[1].to_string();
-    let content = Content::try_from(content_str)?;
+    // A request without headers only has the empty line left
+    let content = match content_str.as_str() {
+      "\r\n" | "\n" => Content::default(),
+      _ => Content::try_from(content_str)?,
+    };
 
     Ok(Request {
       method,
       uri,
+      version,
       content,
+      params: HashMap::new(),
+      reader: BodyReader::empty(),
     })
   }
 }
@@ -89,18 +184,67 @@ impl Display for Request {
     write!(
       fmt,
       "{} {} {}\r\n{}",
-      self.method, self.uri, HTTP_VERSION, self.content
+      self.method, self.uri, self.version, self.content
     )
   }
 }
 
-/// A small enum which encodes the type of http-request.
+/// The versions of http which a request can be sent with.
 #[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HttpVersion {
+  Http10,
+  Http11,
+}
+
+impl FromStr for HttpVersion {
+  type Err = ParseRequestError;
+
+  /// Gives an unsupported version error for versions which are
+  /// well-formed, but not one of the supported versions.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "HTTP/1.0" => Ok(HttpVersion::Http10),
+      HTTP_VERSION => Ok(HttpVersion::Http11),
+      _ => {
+        let is_well_formed = s.starts_with("HTTP/") && {
+          let mut numbers = s[5..].splitn(2, '.');
+          match (numbers.next(), numbers.next()) {
+            (Some(major), Some(minor)) => [major, minor]
+              .iter()
+              .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())),
+            _ => false,
+          }
+        };
+        if is_well_formed {
+          Err(ParseRequestError::unsupported_version())
+        } else {
+          Err(ParseRequestError::invalid())
+        }
+      }
+    }
+  }
+}
+
+impl Display for HttpVersion {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+    let version_str = match *self {
+      HttpVersion::Http10 => "HTTP/1.0",
+      HttpVersion::Http11 => HTTP_VERSION,
+    };
+    write!(fmt, "{}", version_str)
+  }
+}
+
+/// A small enum which encodes the type of http-request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum RequestMethod {
   GET,
   HEAD,
   PUT,
   POST,
+  DELETE,
+  OPTIONS,
+  PATCH,
 }
 
 impl FromStr for RequestMethod {
@@ -113,6 +257,17 @@ impl FromStr for RequestMethod {
       "HEAD" => Ok(HEAD),
       "PUT" => Ok(PUT),
       "POST" => Ok(POST),
+      "DELETE" => Ok(DELETE),
+      "OPTIONS" => Ok(OPTIONS),
+      "PATCH" => Ok(PATCH),
+      // Any other token is a method, just not one which is known
+      _ if !s.is_empty()
+        && s
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)) =>
+      {
+        Err(ParseRequestMethodError::unknown_method())
+      }
       _ => Err(ParseRequestMethodError::invalid()),
     }
   }
@@ -126,6 +281,9 @@ impl Display for RequestMethod {
       HEAD => "HEAD",
       PUT => "PUT",
       POST => "POST",
+      DELETE => "DELETE",
+      OPTIONS => "OPTIONS",
+      PATCH => "PATCH",
     };
     write!(fmt, "{}", method_str)
   }
@@ -145,7 +303,10 @@ mod tests {
     let expected_req = Request {
       method: RequestMethod::GET,
       uri: "/".to_string(),
+      version: HttpVersion::Http11,
       content: Content::default(),
+      params: HashMap::new(),
+      reader: BodyReader::empty(),
     };
 
     assert_eq!(expected_req, req, "Creating Request from String failed");
@@ -165,7 +326,10 @@ mod tests {
     let expected_req = Request {
       method: RequestMethod::GET,
       uri: "/about/us".to_string(),
+      version: HttpVersion::Http11,
       content: expected_cont,
+      params: HashMap::new(),
+      reader: BodyReader::empty(),
     };
 
     assert_eq!(expected_req, req, "Creating Request from String failed");
@@ -184,7 +348,10 @@ mod tests {
     let expected_req = Request {
       method: RequestMethod::PUT,
       uri: "/new".to_string(),
+      version: HttpVersion::Http11,
       content: expected_cont,
+      params: HashMap::new(),
+      reader: BodyReader::empty(),
     };
 
     assert_eq!(expected_req, req, "Creating Request from String failed");
@@ -213,7 +380,10 @@ mod tests {
     let expected_req = Request {
       method: RequestMethod::GET,
       uri: "/about/".to_string(),
+      version: HttpVersion::Http11,
       content: expected_cont,
+      params: HashMap::new(),
+      reader: BodyReader::empty(),
     };
 
     assert_eq!(expected_req, req, "Creation not matching expectation");
@@ -236,4 +406,81 @@ mod tests {
     }
   }
 
+  #[test]
+  fn request_from_string_without_headers() {
+    let req_str = "GET / HTTP/1.1\r\n\r\n".to_string();
+    match Request::try_from(req_str) {
+      Ok(req) => assert_eq!(Request::new(RequestMethod::GET, "/"), req),
+      Err(e) => panic!("Should not get error on valid http: {}", e),
+    }
+  }
+
+  #[test]
+  fn request_from_string_http_1_0() {
+    let req_str = "GET / HTTP/1.0\r\n\r\n".to_string();
+    match Request::try_from(req_str) {
+      Ok(req) => assert_eq!(HttpVersion::Http10, req.version()),
+      Err(e) => panic!("Should not get error on valid http: {}", e),
+    }
+  }
+
+  #[test]
+  fn request_error_kinds() {
+    use errors::ParseErrorKind::*;
+    let invalid = vec![
+      ("GET / HTTP/2.0\r\n\r\n\r\n", UnsupportedVersion),
+      ("GET / HTTPS/1.1\r\n\r\n\r\n", Invalid),
+      ("BREW / HTTP/1.1\r\n\r\n\r\n", UnknownMethod),
+      ("G(T / HTTP/1.1\r\n\r\n\r\n", Invalid),
+    ];
+
+    for (req_str, expected_kind) in invalid {
+      match Request::try_from(req_str.to_string()) {
+        Ok(_) => panic!("Should get error on invalid http: {:?}", req_str),
+        Err(e) => assert_eq!(
+          expected_kind,
+          e.kind(),
+          "Wrong error kind for {:?}",
+          req_str
+        ),
+      }
+    }
+  }
+
+  #[test]
+  fn request_path_and_query() {
+    let req = Request::new(RequestMethod::GET, "/search?q=rust&page=2");
+    assert_eq!("/search", req.path());
+    assert_eq!(Some("q=rust&page=2"), req.query());
+
+    let req = Request::new(RequestMethod::GET, "/about/");
+    assert_eq!("/about/", req.path());
+    assert_eq!(None, req.query());
+  }
+
+  #[test]
+  fn request_body_reader() {
+    let mut req = Request::new(RequestMethod::POST, "/upload");
+    req.set_body("from the string");
+    let mut body = String::new();
+    req
+      .body_reader()
+      .read_to_string(&mut body)
+      .expect("Unable to read body");
+    assert_eq!("from the string", body);
+
+    req.set_body_reader(&b"from the reader"[..]);
+    assert_ne!(Request::new(RequestMethod::POST, "/upload"), req);
+    assert_eq!(req, req);
+    let mut body = String::new();
+    req
+      .body_reader()
+      .read_to_string(&mut body)
+      .expect("Unable to read body");
+    assert_eq!("from the reader", body);
+
+    // Requests can be handled on another thread than the connection
+    fn send_and_sync<T: Send + Sync>(_: &T) {}
+    send_and_sync(&req);
+  }
 }
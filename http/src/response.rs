@@ -1,5 +1,6 @@
 use std::{convert::TryFrom, fmt::{self, Display}, str::FromStr};
 
+use super::body::Body;
 use super::content::{Content, Contentable};
 use super::HTTP_VERSION;
 
@@ -8,10 +9,15 @@ pub use super::errors::{ParseResponseError, ParseStatusCodeError};
 /// A struct which is a representation of a http-response
 /// message. When written to string, it is valid http, which can
 /// be sent directly across a TCP-connection.
-#[derive(Debug, PartialEq)]
+///
+/// A response can instead have a `Body` which is not a string,
+/// such as a file. Only the head is written to string then, and
+/// the body has to be sent after it.
+#[derive(Debug)]
 pub struct Response {
   status_code: StatusCode,
   content: Content,
+  body: Option<Body>,
 }
 
 impl Response {
@@ -19,15 +25,64 @@ impl Response {
     Response {
       status_code,
       content: Content::new(body),
+      body: None,
     }
   }
+
+  /// A response with a body which is sent after the head.
+  pub fn with_body(status_code: StatusCode, body: Body) -> Response {
+    Response {
+      status_code,
+      content: Content::new(""),
+      body: Some(body),
+    }
+  }
+
+  pub fn status_code(&self) -> StatusCode {
+    self.status_code
+  }
+
+  /// Removes the body which is sent after the head, if any.
+  pub fn take_body(&mut self) -> Option<Body> {
+    self.body.take()
+  }
+
+  /// Sends the body after the head instead of the string body,
+  /// which is cleared. A response has only one of the two.
+  pub fn replace_body(&mut self, body: Body) {
+    self.content.set_body("");
+    self.body = Some(body);
+  }
+
+  /// The length of the body, whether it is a string or not,
+  /// or `None` if the length is not known before it is sent.
+  pub fn content_length(&self) -> Option<u64> {
+    match self.body {
+      Some(ref body) => body.length(),
+      None => Some(self.content.get_body().len() as u64),
+    }
+  }
+}
+
+/// Responses with a `Body` are never equal, since their
+/// bodies cannot be compared without reading them.
+impl PartialEq for Response {
+  fn eq(&self, other: &Response) -> bool {
+    self.status_code == other.status_code
+      && self.content == other.content
+      && self.body.is_none()
+      && other.body.is_none()
+  }
 }
 
 impl Contentable for Response {
   fn get_body(&self) -> &str {
     self.content.get_body()
   }
+  /// Sets the string body, and removes the body which would
+  /// have been sent after the head, if any.
   fn set_body<S: Into<String>>(&mut self, new_body: S) -> String {
+    self.body = None;
     self.content.set_body(new_body)
   }
   fn has_header(&self, name: &str) -> Option<&str> {
@@ -36,6 +91,9 @@ impl Contentable for Response {
   fn add_header<S: Into<String>>(&mut self, name: S, value: S) -> Option<String> {
     self.content.add_header(name, value)
   }
+  fn remove_header(&mut self, name: &str) -> Option<String> {
+    self.content.remove_header(name)
+  }
 }
 
 impl TryFrom<String> for Response {
@@ -69,6 +127,7 @@ impl TryFrom<String> for Response {
     Ok(Response {
       status_code,
       content,
+      body: None,
     })
   }
 }
@@ -77,12 +136,16 @@ impl Display for Response {
   fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
     write!(
       fmt,
-      "{} {} {}\r\n{}",
+      "{} {} {}\r\n",
       HTTP_VERSION,
       self.status_code,
-      self.status_code.to_reason_phrase(),
-      self.content
-    )
+      self.status_code.to_reason_phrase()
+    )?;
+    if self.status_code.allows_body() {
+      write!(fmt, "{}", self.content)
+    } else {
+      self.content.fmt_headers(fmt)
+    }
   }
 }
 
@@ -105,6 +168,7 @@ pub enum StatusCode {
   NotModified = 304,
   UseProxy = 305,
   TemporaryRedirect = 307,
+  PermanentRedirect = 308,
   BadRequest = 400,
   Unauthorized = 401,
   PaymentRequired = 402,
@@ -123,6 +187,7 @@ pub enum StatusCode {
   UnsupportedMediaType = 415,
   Requestedrangenotsatisfiable = 416,
   ExpectationFailed = 417,
+  RequestHeaderFieldsTooLarge = 431,
   InternalServerError = 500,
   NotImplemented = 501,
   BadGateway = 502,
@@ -132,6 +197,16 @@ pub enum StatusCode {
 }
 
 impl StatusCode {
+  /// Whether a response with the status can have a body. Responses
+  /// with `1xx`, `204` and `304` never have one (RFC 7230, 3.3.3).
+  pub fn allows_body(&self) -> bool {
+    match *self {
+      StatusCode::Continue | StatusCode::SwitchingProtocols => false,
+      StatusCode::NoContent | StatusCode::NotModified => false,
+      _ => true,
+    }
+  }
+
   pub fn to_reason_phrase(&self) -> &str {
     use self::StatusCode::*;
     match *self {
@@ -151,6 +226,7 @@ impl StatusCode {
       NotModified => "Not Modified",
       UseProxy => "Use Proxy",
       TemporaryRedirect => "Temporary Redirect",
+      PermanentRedirect => "Permanent Redirect",
       BadRequest => "Bad Request",
       Unauthorized => "Unauthorized",
       PaymentRequired => "Payment Required",
@@ -169,6 +245,7 @@ impl StatusCode {
       UnsupportedMediaType => "Unsupported Media Type",
       Requestedrangenotsatisfiable => "Requested range not satisfiable",
       ExpectationFailed => "Expectation Failed",
+      RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
       InternalServerError => "Internal Server Error",
       NotImplemented => "Not Implemented",
       BadGateway => "Bad Gateway",
@@ -201,6 +278,7 @@ impl TryFrom<u16> for StatusCode {
       num if num == NotModified as u16 => Ok(NotModified),
       num if num == UseProxy as u16 => Ok(UseProxy),
       num if num == TemporaryRedirect as u16 => Ok(TemporaryRedirect),
+      num if num == PermanentRedirect as u16 => Ok(PermanentRedirect),
       num if num == BadRequest as u16 => Ok(BadRequest),
       num if num == Unauthorized as u16 => Ok(Unauthorized),
       num if num == PaymentRequired as u16 => Ok(PaymentRequired),
@@ -219,6 +297,7 @@ impl TryFrom<u16> for StatusCode {
       num if num == UnsupportedMediaType as u16 => Ok(UnsupportedMediaType),
       num if num == Requestedrangenotsatisfiable as u16 => Ok(Requestedrangenotsatisfiable),
       num if num == ExpectationFailed as u16 => Ok(ExpectationFailed),
+      num if num == RequestHeaderFieldsTooLarge as u16 => Ok(RequestHeaderFieldsTooLarge),
       num if num == InternalServerError as u16 => Ok(InternalServerError),
       num if num == NotImplemented as u16 => Ok(NotImplemented),
       num if num == BadGateway as u16 => Ok(BadGateway),
@@ -257,6 +336,7 @@ mod tests {
     let expected_res = Response {
       status_code: StatusCode::OK,
       content: Content::new("hello world"),
+      body: None,
     };
 
     assert_eq!(expected_res, res);
@@ -271,6 +351,58 @@ mod tests {
     assert_eq!(expected_str, res.to_string());
   }
 
+  #[test]
+  fn no_body_for_204_and_304() {
+    let res = Response::new(StatusCode::NoContent, "ignored");
+    assert_eq!(
+      format!("{} 204 No Content\r\n\r\n", HTTP_VERSION),
+      res.to_string()
+    );
+
+    let res = Response::new(StatusCode::NotModified, "ignored");
+    assert_eq!(
+      format!("{} 304 Not Modified\r\n\r\n", HTTP_VERSION),
+      res.to_string()
+    );
+  }
+
+  #[test]
+  fn body_is_not_written_with_the_head() {
+    let file = ::std::fs::File::open("Cargo.toml").expect("Unable to open file");
+    let length = file.metadata().expect("Unable to read metadata").len();
+    let mut res = Response::with_body(
+      StatusCode::OK,
+      Body::file(file).expect("Unable to open body"),
+    );
+
+    assert_eq!(format!("{} 200 OK\r\n\r\n", HTTP_VERSION), res.to_string());
+    assert_eq!(Some(length), res.content_length());
+    assert_ne!(Response::new(StatusCode::OK, ""), res);
+    assert_eq!(
+      Some(Some(length)),
+      res.take_body().map(|body| body.length())
+    );
+    assert_eq!(Some(0), res.content_length());
+
+    let res = Response::with_body(
+      StatusCode::OK,
+      Body::chunks(vec![b"a".to_vec()].into_iter()),
+    );
+    assert_eq!(None, res.content_length());
+  }
+
+  #[test]
+  fn only_one_kind_of_body() {
+    let mut res = Response::with_body(StatusCode::OK, Body::Bytes(b"bytes".to_vec()));
+    res.set_body("text");
+    assert_eq!(Some(4), res.content_length());
+    assert!(res.take_body().is_none());
+
+    res.replace_body(Body::Bytes(b"bytes".to_vec()));
+    assert_eq!("", res.get_body());
+    assert_eq!(Some(5), res.content_length());
+  }
+
   #[test]
   fn use_headers() {
     let mut res = Response::new(StatusCode::OK, "hello world");
@@ -0,0 +1,239 @@
+use std::{fs::{File, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, sync::Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use config::{LogFormat, LogLevel, LoggingConfig};
+use logger::Context;
+use utils::{self, DateTime};
+
+/// Set when the log file should be reopened, which is done
+/// before the next line is written.
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+/// What is logged about a request which has been answered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+  pub client: String,
+  pub time: SystemTime,
+  /// The request line, which is unknown when the
+  /// request could not be parsed.
+  pub request_line: Option<String>,
+  pub status: u16,
+  /// The size of the body of the response.
+  pub bytes: usize,
+  pub referer: Option<String>,
+  pub user_agent: Option<String>,
+  pub duration: Duration,
+}
+
+enum Output {
+  Stdout,
+  File(PathBuf, File),
+}
+
+/// Writes a line for every request to stdout or to a file.
+/// The file is reopened after `SIGHUP`, so that it can be
+/// rotated while the server is running.
+pub struct AccessLog {
+  format: LogFormat,
+  output: Mutex<Output>,
+}
+
+impl AccessLog {
+  pub fn new(config: &LoggingConfig) -> io::Result<AccessLog> {
+    let output = match config.access_log {
+      Some(ref path) => Output::File(path.clone(), open(path)?),
+      None => Output::Stdout,
+    };
+    Ok(AccessLog {
+      format: config.format,
+      output: Mutex::new(output),
+    })
+  }
+
+  pub fn log(&self, entry: &Entry) {
+    let line = format_entry(self.format, entry);
+    let mut output = match self.output.lock() {
+      Ok(output) => output,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+
+    let result = match *output {
+      Output::Stdout => writeln!(io::stdout(), "{}", line),
+      Output::File(ref path, ref mut file) => {
+        if REOPEN.swap(false, Ordering::SeqCst) {
+          match open(path) {
+            Ok(reopened) => *file = reopened,
+            Err(e) => log!(
+              LogLevel::Error,
+              "access_log",
+              &Context::default(),
+              "unable to reopen {}: {}",
+              path.display(),
+              e
+            ),
+          }
+        }
+        writeln!(file, "{}", line)
+      }
+    };
+    if let Err(e) = result {
+      log!(
+        LogLevel::Error,
+        "access_log",
+        &Context::default(),
+        "unable to write: {}",
+        e
+      );
+    }
+  }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+  OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Makes `SIGHUP` reopen the access log.
+#[cfg(unix)]
+pub fn reopen_on_sighup() {
+  extern "C" fn on_sighup(_: ::libc::c_int) {
+    REOPEN.store(true, Ordering::SeqCst);
+  }
+
+  let handler = on_sighup as extern "C" fn(::libc::c_int);
+  unsafe {
+    ::libc::signal(::libc::SIGHUP, handler as ::libc::sighandler_t);
+  }
+}
+
+#[cfg(not(unix))]
+pub fn reopen_on_sighup() {}
+
+fn format_entry(format: LogFormat, entry: &Entry) -> String {
+  let time = DateTime::from_system_time(entry.time);
+  let request_line = entry.request_line.as_ref().map(|s| s.as_str());
+
+  match format {
+    LogFormat::Common => format!(
+      "{} - - [{}] {} {} {}",
+      entry.client,
+      time.to_clf(),
+      quote(request_line),
+      entry.status,
+      bytes(entry.bytes)
+    ),
+    LogFormat::Combined => format!(
+      "{} - - [{}] {} {} {} {} {}",
+      entry.client,
+      time.to_clf(),
+      quote(request_line),
+      entry.status,
+      bytes(entry.bytes),
+      quote(entry.referer.as_ref().map(|s| s.as_str())),
+      quote(entry.user_agent.as_ref().map(|s| s.as_str()))
+    ),
+    LogFormat::Json => {
+      let json_or_null = |value: Option<&str>| value.map_or("null".to_string(), utils::json_string);
+      let duration_ms =
+        entry.duration.as_secs() as f64 * 1000.0 + f64::from(entry.duration.subsec_nanos()) / 1e6;
+      format!(
+        concat!(
+          "{{\"time\":\"{}\",\"client\":{},\"request\":{},\"status\":{},\"bytes\":{},",
+          "\"referer\":{},\"user_agent\":{},\"duration_ms\":{:.3}}}"
+        ),
+        time.to_rfc3339(),
+        utils::json_string(&entry.client),
+        json_or_null(request_line),
+        entry.status,
+        entry.bytes,
+        json_or_null(entry.referer.as_ref().map(|s| s.as_str())),
+        json_or_null(entry.user_agent.as_ref().map(|s| s.as_str())),
+        duration_ms
+      )
+    }
+  }
+}
+
+/// Quotes a field of the Common Log Format, where a
+/// missing field is written as `"-"`.
+fn quote(value: Option<&str>) -> String {
+  let value = value.unwrap_or("-");
+  format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The size of the response, where no body is written as `-`.
+fn bytes(bytes: usize) -> String {
+  if bytes == 0 {
+    "-".to_string()
+  } else {
+    bytes.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::UNIX_EPOCH;
+
+  fn entry() -> Entry {
+    Entry {
+      client: "127.0.0.1".to_string(),
+      // 10/Oct/2000:13:55:36 +0000
+      time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+      request_line: Some("GET /apache_pb.gif HTTP/1.0".to_string()),
+      status: 200,
+      bytes: 2326,
+      referer: Some("http://www.example.com/start.html".to_string()),
+      user_agent: Some("Mozilla/4.08 \"test\"".to_string()),
+      duration: Duration::from_millis(12),
+    }
+  }
+
+  #[test]
+  fn format_common_and_combined() {
+    assert_eq!(
+      "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326",
+      format_entry(LogFormat::Common, &entry())
+    );
+    assert_eq!(
+      "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 \
+       \"http://www.example.com/start.html\" \"Mozilla/4.08 \\\"test\\\"\"",
+      format_entry(LogFormat::Combined, &entry())
+    );
+
+    let unparsed = Entry {
+      request_line: None,
+      status: 400,
+      bytes: 0,
+      ..entry()
+    };
+    assert_eq!(
+      "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"-\" 400 -",
+      format_entry(LogFormat::Common, &unparsed)
+    );
+  }
+
+  #[test]
+  fn format_json() {
+    let entry = Entry {
+      referer: None,
+      ..entry()
+    };
+    assert_eq!(
+      "{\"time\":\"2000-10-10T13:55:36Z\",\"client\":\"127.0.0.1\",\
+       \"request\":\"GET /apache_pb.gif HTTP/1.0\",\"status\":200,\"bytes\":2326,\
+       \"referer\":null,\"user_agent\":\"Mozilla/4.08 \\\"test\\\"\",\"duration_ms\":12.000}",
+      format_entry(LogFormat::Json, &entry)
+    );
+  }
+
+  #[test]
+  fn dates_before_and_after_leap_days() {
+    let date =
+      |secs| DateTime::from_system_time(UNIX_EPOCH + Duration::from_secs(secs)).to_rfc3339();
+    assert_eq!("1970-01-01T00:00:00Z", date(0));
+    assert_eq!("2000-02-29T23:59:59Z", date(951_868_799));
+    assert_eq!("2000-03-01T00:00:00Z", date(951_868_800));
+    assert_eq!("2024-12-31T12:00:00Z", date(1_735_646_400));
+  }
+}
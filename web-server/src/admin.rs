@@ -0,0 +1,309 @@
+use std::{fmt::Write, path::PathBuf, sync::{Arc, RwLock}, time::Duration};
+
+use http::{self, content::Contentable};
+use config::{AdminConfig, Config};
+use handler::Handler;
+use files::FileCache;
+use health::Health;
+use metrics::Metrics;
+use router::{self, Routes};
+
+/// What the admin pages are made from.
+#[derive(Clone)]
+pub struct Admin {
+  pub metrics: Arc<Metrics>,
+  pub health: Arc<Health>,
+  pub cache: Arc<FileCache>,
+  pub status: Arc<RwLock<Status>>,
+}
+
+/// What the status page shows about the configuration,
+/// the routes and the sites.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+  config: Vec<(&'static str, String)>,
+  routes: Vec<String>,
+  sites: Vec<SiteStatus>,
+}
+
+#[derive(Debug, Clone)]
+struct SiteStatus {
+  root: PathBuf,
+  hosts: Vec<String>,
+  listen: Vec<String>,
+}
+
+impl Status {
+  /// Describes the configuration and the routes every site has.
+  pub fn new(config: &Config, routes: &Routes) -> Status {
+    let listeners = config.listeners();
+    let sites = config
+      .sites
+      .iter()
+      .enumerate()
+      .map(|(i, site)| SiteStatus {
+        root: site.root.clone(),
+        hosts: site.hosts.clone(),
+        listen: listeners
+          .iter()
+          .filter(|&&(_, ref sites)| sites.contains(&i))
+          .map(|&(ref address, _)| address.clone())
+          .collect(),
+      })
+      .collect();
+
+    let timeouts = &config.timeouts;
+    let access_log = match config.logging.access_log {
+      Some(ref path) => path.display().to_string(),
+      None => "stdout".to_string(),
+    };
+    Status {
+      config: vec![
+        ("threads", config.threads.to_string()),
+        ("dev", config.dev.to_string()),
+        ("log level", config.logging.level.to_string()),
+        (
+          "access log",
+          format!("{} ({})", access_log, config.logging.format),
+        ),
+        (
+          "max body size",
+          format!("{} bytes", config.limits.max_body_size),
+        ),
+        (
+          "file cache",
+          format!(
+            "{} bytes, files up to {} bytes",
+            config.cache.max_size, config.cache.max_file_size
+          ),
+        ),
+        (
+          "timeouts",
+          format!(
+            "head {}s, body {}s, idle {}s, write {}s, shutdown {}s",
+            timeouts.head.as_secs(),
+            timeouts.body.as_secs(),
+            timeouts.idle.as_secs(),
+            timeouts.write.as_secs(),
+            timeouts.shutdown.as_secs()
+          ),
+        ),
+      ],
+      routes: router::describe_routes(routes),
+      sites,
+    }
+  }
+}
+
+/// Adds `/healthz`, which answers as long as the server runs, and
+/// `/readyz`, which fails while the server is not ready.
+pub fn add_health_routes(routes: &mut Routes, health: Arc<Health>) {
+  add(routes, "/healthz", |_: &http::Request| {
+    http::Response::new(http::StatusCode::OK, "ok\n")
+  });
+  add(routes, "/readyz", move |_: &http::Request| {
+    match health.not_ready_reason() {
+      None => http::Response::new(http::StatusCode::OK, "ready\n"),
+      Some(reason) => http::Response::new(
+        http::StatusCode::ServiceUnavailable,
+        format!("{}\n", reason),
+      ),
+    }
+  });
+}
+
+/// Adds the admin pages under the path of the configuration.
+pub fn add_routes(routes: &mut Routes, config: &AdminConfig, admin: &Admin) {
+  let metrics = Arc::clone(&admin.metrics);
+  let render_metrics = move |_: &http::Request| {
+    let mut response = http::Response::new(http::StatusCode::OK, metrics.render());
+    response.add_header("Content-Type", "text/plain; version=0.0.4");
+    response
+  };
+  add(routes, &format!("{}/metrics", config.path), render_metrics);
+
+  let admin = admin.clone();
+  let render_status = move |_: &http::Request| {
+    let mut response = http::Response::new(http::StatusCode::OK, render_status(&admin));
+    response.add_header("Content-Type", "text/plain; charset=utf-8");
+    response
+  };
+  add(routes, &format!("{}/status", config.path), render_status);
+}
+
+/// The routes which are served while the sites are loaded. The
+/// health pages tell that the server is loading, and every other
+/// page is unavailable until the sites are served instead.
+pub fn loading_routes(health: Arc<Health>) -> Routes {
+  let mut routes = Routes::new();
+  add_health_routes(&mut routes, health);
+  add(&mut routes, "/*rest", |_: &http::Request| {
+    http::Response::new(http::StatusCode::ServiceUnavailable, "")
+  });
+  routes
+}
+
+fn add<H: Handler + 'static>(routes: &mut Routes, pattern: &str, handler: H) {
+  if let Err(e) = router::add_route(routes, http::RequestMethod::GET, pattern, Arc::new(handler)) {
+    panic!("Unable to add admin page: {}", e);
+  }
+}
+
+fn render_status(admin: &Admin) -> String {
+  let mut out = String::new();
+  let _ = writeln!(
+    out,
+    "status: {}",
+    admin.health.not_ready_reason().unwrap_or("ready")
+  );
+  let _ = writeln!(out, "uptime: {}", format_duration(admin.health.uptime()));
+  let _ = writeln!(
+    out,
+    "connections: {} active, {} total",
+    admin.metrics.active_connections(),
+    admin.metrics.total_connections()
+  );
+  let (cached_files, cached_bytes) = admin.cache.usage();
+  let _ = writeln!(
+    out,
+    "file cache: {} files, {} bytes",
+    cached_files, cached_bytes
+  );
+
+  let status = match admin.status.read() {
+    Ok(status) => status,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  let _ = writeln!(out, "\nconfiguration");
+  for &(name, ref value) in &status.config {
+    let _ = writeln!(out, "  {}: {}", name, value);
+  }
+
+  let _ = writeln!(out, "\nsites");
+  for site in &status.sites {
+    let hosts = if site.hosts.is_empty() {
+      "default".to_string()
+    } else {
+      site.hosts.join(", ")
+    };
+    let _ = writeln!(
+      out,
+      "  {} on {} ({})",
+      site.root.display(),
+      site.listen.join(", "),
+      hosts
+    );
+  }
+
+  let _ = writeln!(out, "\nroutes");
+  for route in &status.routes {
+    let _ = writeln!(out, "  {}", route);
+  }
+  out
+}
+
+/// Formats a duration such as `2d 3h 4m 5s`, leaving
+/// out the units before the first one which is not zero.
+fn format_duration(duration: Duration) -> String {
+  let secs = duration.as_secs();
+  let parts = [
+    (secs / 86400, "d"),
+    (secs / 3600 % 24, "h"),
+    (secs / 60 % 60, "m"),
+  ];
+  let mut out = String::new();
+  for &(value, unit) in &parts {
+    if value > 0 || !out.is_empty() {
+      let _ = write!(out, "{}{} ", value, unit);
+    }
+  }
+  let _ = write!(out, "{}s", secs % 60);
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::convert::TryFrom;
+  use logger::Context;
+
+  fn get(routes: &Routes, path: &str) -> http::Response {
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    let request = http::Request::try_from(request).expect("Unable to parse request");
+    router::Router::from_routes(routes.clone()).handle_request(request, &Context::default())
+  }
+
+  #[test]
+  fn health_and_status_pages() {
+    let config = Config::default();
+    let admin = Admin {
+      metrics: Arc::new(Metrics::new()),
+      health: Arc::new(Health::new()),
+      cache: Arc::new(FileCache::new(config.cache)),
+      status: Arc::new(RwLock::new(Status::default())),
+    };
+    let mut routes = Routes::new();
+    add_health_routes(&mut routes, Arc::clone(&admin.health));
+    add_routes(&mut routes, &AdminConfig::default(), &admin);
+    *admin.status.write().unwrap() = Status::new(&config, &routes);
+
+    assert_eq!("ready\n", get(&routes, "/readyz").get_body());
+    let body = get(&routes, "/_admin/status").get_body().to_string();
+    for line in &[
+      "status: ready",
+      "  threads: 4",
+      "  GET, HEAD /_admin/metrics",
+      "  GET, HEAD /readyz",
+    ] {
+      assert!(
+        body.lines().any(|l| l == *line),
+        "Missing line: {}\n{}",
+        line,
+        body
+      );
+    }
+    assert!(
+      body.contains("./html/ on localhost:8080 (default)"),
+      "{}",
+      body
+    );
+
+    admin.health.stop();
+    let res = get(&routes, "/readyz");
+    assert_eq!(http::StatusCode::ServiceUnavailable, res.status_code());
+    assert_eq!("ok\n", get(&routes, "/healthz").get_body());
+  }
+
+  #[test]
+  fn unavailable_while_loading() {
+    let health = Arc::new(Health::new());
+    let routes = loading_routes(Arc::clone(&health));
+    let loading = health.loading();
+
+    let res = get(&routes, "/readyz");
+    assert_eq!(http::StatusCode::ServiceUnavailable, res.status_code());
+    assert_eq!("loading\n", res.get_body());
+    assert_eq!("ok\n", get(&routes, "/healthz").get_body());
+    assert_eq!(
+      http::StatusCode::ServiceUnavailable,
+      get(&routes, "/").status_code()
+    );
+    assert_eq!(
+      http::StatusCode::ServiceUnavailable,
+      get(&routes, "/about/us/").status_code()
+    );
+
+    drop(loading);
+    assert_eq!("ready\n", get(&routes, "/readyz").get_body());
+  }
+
+  #[test]
+  fn format_uptime() {
+    assert_eq!("5s", format_duration(Duration::from_secs(5)));
+    assert_eq!("1h 0m 5s", format_duration(Duration::from_secs(3605)));
+    assert_eq!(
+      "2d 0h 0m 0s",
+      format_duration(Duration::from_secs(2 * 86400))
+    );
+  }
+}
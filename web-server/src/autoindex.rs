@@ -0,0 +1,287 @@
+use std::{cmp::Ordering, fmt::Write, time::SystemTime};
+
+use http::{self, content::Contentable};
+use utils::{self, DateTime};
+
+/// A file or directory in the listing of a directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+  pub name: String,
+  pub is_dir: bool,
+  pub size: u64,
+  pub modified: Option<SystemTime>,
+}
+
+/// What a listing is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+  Name,
+  Size,
+  Modified,
+}
+
+impl SortKey {
+  fn name(&self) -> &'static str {
+    match *self {
+      SortKey::Name => "name",
+      SortKey::Size => "size",
+      SortKey::Modified => "modified",
+    }
+  }
+}
+
+/// How a listing is sorted, as asked for in the query
+/// of the request, such as `?sort=size&order=desc`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sort {
+  key: SortKey,
+  descending: bool,
+}
+
+impl Sort {
+  /// Reads the sort from the query. Values which are not
+  /// known are left at sorting by name, ascending.
+  fn from_query(query: Option<&str>) -> Sort {
+    let mut sort = Sort {
+      key: SortKey::Name,
+      descending: false,
+    };
+    for pair in query.unwrap_or("").split('&') {
+      let mut parts = pair.splitn(2, '=');
+      match (parts.next(), parts.next()) {
+        (Some("sort"), Some("name")) => sort.key = SortKey::Name,
+        (Some("sort"), Some("size")) => sort.key = SortKey::Size,
+        (Some("sort"), Some("modified")) => sort.key = SortKey::Modified,
+        (Some("order"), Some("asc")) => sort.descending = false,
+        (Some("order"), Some("desc")) => sort.descending = true,
+        _ => {}
+      }
+    }
+    sort
+  }
+
+  /// Directories come before files either way, and
+  /// entries which are equal are sorted by name.
+  fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+    let ordering = match self.key {
+      SortKey::Name => Ordering::Equal,
+      SortKey::Size => a.size.cmp(&b.size),
+      SortKey::Modified => a.modified.cmp(&b.modified),
+    }
+    .then_with(|| a.name.cmp(&b.name));
+    let ordering = if self.descending {
+      ordering.reverse()
+    } else {
+      ordering
+    };
+    b.is_dir.cmp(&a.is_dir).then(ordering)
+  }
+
+  /// The query which sorts by the key, in the other order
+  /// when the listing is already sorted by it.
+  fn query_for(&self, key: SortKey) -> String {
+    let order = if key == self.key && !self.descending {
+      "desc"
+    } else {
+      "asc"
+    };
+    format!("?sort={}&order={}", key.name(), order)
+  }
+}
+
+/// Answers with the listing of the directory at the path of the
+/// request, as JSON when the client asks for it and as HTML otherwise.
+pub fn respond(request: &http::Request, mut entries: Vec<Entry>) -> http::Response {
+  let sort = Sort::from_query(request.query());
+  entries.sort_by(|a, b| sort.compare(a, b));
+  let path = utils::percent_decode(request.path())
+    .map(|path| String::from_utf8_lossy(&path).into_owned())
+    .unwrap_or_else(|| request.path().to_string());
+
+  let (body, content_type) = if request
+    .has_header("Accept")
+    .map_or(false, utils::prefers_json)
+  {
+    (render_json(&path, &entries), "application/json")
+  } else {
+    (
+      render_html(&path, &entries, sort),
+      "text/html; charset=utf-8",
+    )
+  };
+  let mut response = http::Response::new(http::StatusCode::OK, body);
+  response.add_header("Content-Type", content_type);
+  response.add_header("Vary", "Accept");
+  response
+}
+
+fn format_time(time: Option<SystemTime>) -> Option<String> {
+  time.map(|time| DateTime::from_system_time(time).to_rfc3339())
+}
+
+fn render_html(path: &str, entries: &[Entry], sort: Sort) -> String {
+  let title = format!("Index of {}", utils::html_escape(path));
+  let mut out = String::new();
+  let _ = write!(
+    out,
+    concat!(
+      "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n",
+      "<body>\n<h1>{0}</h1>\n<table>\n"
+    ),
+    title
+  );
+  let _ = writeln!(
+    out,
+    concat!(
+      "<tr><th><a href=\"{}\">Name</a></th><th><a href=\"{}\">Size</a></th>",
+      "<th><a href=\"{}\">Modified</a></th></tr>"
+    ),
+    utils::html_escape(&sort.query_for(SortKey::Name)),
+    utils::html_escape(&sort.query_for(SortKey::Size)),
+    utils::html_escape(&sort.query_for(SortKey::Modified))
+  );
+  if path != "/" {
+    let _ = writeln!(
+      out,
+      "<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>"
+    );
+  }
+  for entry in entries {
+    let suffix = if entry.is_dir { "/" } else { "" };
+    let size = if entry.is_dir {
+      "-".to_string()
+    } else {
+      entry.size.to_string()
+    };
+    let _ = writeln!(
+      out,
+      "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+      utils::percent_encode(&entry.name),
+      suffix,
+      utils::html_escape(&entry.name),
+      suffix,
+      size,
+      format_time(entry.modified).unwrap_or_else(|| "-".to_string())
+    );
+  }
+  out.push_str("</table>\n</body>\n</html>\n");
+  out
+}
+
+fn render_json(path: &str, entries: &[Entry]) -> String {
+  let entries: Vec<String> = entries
+    .iter()
+    .map(|entry| {
+      format!(
+        "{{\"name\":{},\"type\":\"{}\",\"size\":{},\"modified\":{}}}",
+        utils::json_string(&entry.name),
+        if entry.is_dir { "directory" } else { "file" },
+        entry.size,
+        format_time(entry.modified).map_or("null".to_string(), |time| utils::json_string(&time))
+      )
+    })
+    .collect();
+  format!(
+    "{{\"path\":{},\"entries\":[{}]}}\n",
+    utils::json_string(path),
+    entries.join(",")
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::{Duration, UNIX_EPOCH};
+
+  fn entries() -> Vec<Entry> {
+    let entry = |name: &str, is_dir, size, secs| Entry {
+      name: name.to_string(),
+      is_dir,
+      size,
+      modified: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+    };
+    vec![
+      entry("b.txt", false, 10, 3),
+      entry("docs", true, 4096, 1),
+      entry("a <&>.txt", false, 30, 2),
+    ]
+  }
+
+  fn names(request: &http::Request) -> Vec<String> {
+    let mut entries = entries();
+    let sort = Sort::from_query(request.query());
+    entries.sort_by(|a, b| sort.compare(a, b));
+    entries.into_iter().map(|entry| entry.name).collect()
+  }
+
+  #[test]
+  fn sort_by_query() {
+    let get = |uri| http::Request::new(http::RequestMethod::GET, uri);
+    assert_eq!(vec!["docs", "a <&>.txt", "b.txt"], names(&get("/files/")));
+    assert_eq!(
+      vec!["docs", "b.txt", "a <&>.txt"],
+      names(&get("/files/?sort=name&order=desc"))
+    );
+    assert_eq!(
+      vec!["docs", "b.txt", "a <&>.txt"],
+      names(&get("/files/?sort=size"))
+    );
+    assert_eq!(
+      vec!["docs", "b.txt", "a <&>.txt"],
+      names(&get("/files/?order=desc&sort=modified"))
+    );
+    assert_eq!(
+      vec!["docs", "a <&>.txt", "b.txt"],
+      names(&get("/files/?sort=owner&order=up"))
+    );
+  }
+
+  #[test]
+  fn listing_as_html_and_json() {
+    let mut request = http::Request::new(http::RequestMethod::GET, "/my%20files/?sort=size");
+    let response = respond(&request, entries());
+    assert_eq!(
+      Some("text/html; charset=utf-8"),
+      response.has_header("Content-Type")
+    );
+    let body = response.get_body();
+    assert!(
+      body.contains("<title>Index of /my files/</title>"),
+      "{}",
+      body
+    );
+    assert!(
+      body.contains("<a href=\"?sort=size&amp;order=desc\">Size</a>"),
+      "{}",
+      body
+    );
+    assert!(
+      body.contains("<a href=\"docs/\">docs/</a></td><td>-</td><td>1970-01-01T00:00:01Z</td>"),
+      "{}",
+      body
+    );
+    assert!(
+      body.contains("<a href=\"a%20%3C%26%3E.txt\">a &lt;&amp;&gt;.txt</a></td><td>30</td>"),
+      "{}",
+      body
+    );
+
+    request.add_header("Accept", "application/json");
+    let response = respond(&request, entries());
+    assert_eq!(
+      Some("application/json"),
+      response.has_header("Content-Type")
+    );
+    assert_eq!(
+      concat!(
+        "{\"path\":\"/my files/\",\"entries\":[",
+        "{\"name\":\"docs\",\"type\":\"directory\",\"size\":4096,",
+        "\"modified\":\"1970-01-01T00:00:01Z\"},",
+        "{\"name\":\"b.txt\",\"type\":\"file\",\"size\":10,\"modified\":\"1970-01-01T00:00:03Z\"},",
+        "{\"name\":\"a <&>.txt\",\"type\":\"file\",\"size\":30,",
+        "\"modified\":\"1970-01-01T00:00:02Z\"}]}\n"
+      ),
+      response.get_body()
+    );
+  }
+}
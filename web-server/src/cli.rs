@@ -0,0 +1,272 @@
+use std::{net::IpAddr, path::PathBuf, str::FromStr};
+
+use super::config::{LogFormat, LogLevel};
+use super::errors::ParseArgsError;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const USAGE: &str = "\
+Usage: web-server [OPTIONS]
+
+Serves the files in a directory over http.
+
+Options:
+  -p, --port <PORT>          Port to listen on [default: 8080]
+  -b, --bind <ADDRESS>       Address to bind to [default: localhost]
+  -r, --root <DIR>           Directory to serve files from [default: ./html/]
+  -t, --threads <NUM>        Number of worker threads [default: 4]
+  -c, --config <FILE>        Read configuration from a file
+      --log-format <FORMAT>  Format of the access log: common, combined or json
+                             [default: common]
+      --log-level <LEVEL>    Least severe messages to log: error, warn, info, debug or trace
+                             [default: info]
+      --index <NAMES>        Comma separated list of index file names [default: index.html]
+      --dev                  Reload files when they change
+      --check-config         Validate the configuration and exit
+  -h, --help                 Print this help and exit
+  -V, --version              Print the version and exit
+";
+
+/// Returns the help text which is printed on `--help`.
+pub fn usage() -> &'static str {
+  USAGE
+}
+
+/// What the binary should do after the command-line
+/// arguments has been parsed.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+  Run(Options),
+  Help,
+  Version,
+}
+
+/// Options given on the command-line. Options which are
+/// not given are `None`, so that they can fall back to the
+/// configuration file or to the defaults in `Config`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+  pub port: Option<u16>,
+  pub bind: Option<String>,
+  pub root: Option<PathBuf>,
+  pub threads: Option<usize>,
+  pub config: Option<PathBuf>,
+  pub log_format: Option<LogFormat>,
+  pub log_level: Option<LogLevel>,
+  pub index: Option<Vec<String>>,
+  pub dev: bool,
+  pub check_config: bool,
+}
+
+/// Parses the command-line arguments, excluding the name of
+/// the binary. Both `--flag value` and `--flag=value` are
+/// accepted for flags which take a value.
+pub fn parse_args<I, S>(args: I) -> Result<Action, ParseArgsError>
+where
+  I: IntoIterator<Item = S>,
+  S: Into<String>,
+{
+  let mut options = Options::default();
+  let mut args = args.into_iter().map(|s| s.into());
+
+  while let Some(arg) = args.next() {
+    let (flag, inline_value) = match arg.find('=') {
+      Some(pos) if arg.starts_with("--") => {
+        (arg[..pos].to_string(), Some(arg[pos + 1..].to_string()))
+      }
+      _ => (arg.clone(), None),
+    };
+
+    match flag.as_str() {
+      "-h" | "--help" => return Ok(Action::Help),
+      "-V" | "--version" => return Ok(Action::Version),
+      "--dev" => options.dev = true,
+      "--check-config" => options.check_config = true,
+      "-p" | "--port" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        options.port = Some(match value.parse::<u16>() {
+          Ok(port) if port != 0 => port,
+          _ => {
+            return Err(ParseArgsError::invalid(
+              &flag,
+              &value,
+              "expected a port between 1 and 65535",
+            ))
+          }
+        });
+      }
+      "-b" | "--bind" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        if value != "localhost" && value.parse::<IpAddr>().is_err() {
+          return Err(ParseArgsError::invalid(
+            &flag,
+            &value,
+            "expected an ip address or localhost",
+          ));
+        }
+        options.bind = Some(value);
+      }
+      "-r" | "--root" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        let root = PathBuf::from(&value);
+        if !root.is_dir() {
+          return Err(ParseArgsError::invalid(&flag, &value, "no such directory"));
+        }
+        options.root = Some(root);
+      }
+      "-t" | "--threads" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        options.threads = Some(match value.parse::<usize>() {
+          Ok(threads) if threads > 0 => threads,
+          _ => {
+            return Err(ParseArgsError::invalid(
+              &flag,
+              &value,
+              "expected a positive number",
+            ))
+          }
+        });
+      }
+      "-c" | "--config" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        options.config = Some(PathBuf::from(value));
+      }
+      "--log-format" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        options.log_format = Some(LogFormat::from_str(&value).map_err(|_| {
+          ParseArgsError::invalid(&flag, &value, "expected common, combined or json")
+        })?);
+      }
+      "--log-level" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        options.log_level = Some(LogLevel::from_str(&value).map_err(|_| {
+          ParseArgsError::invalid(&flag, &value, "expected error, warn, info, debug or trace")
+        })?);
+      }
+      "--index" => {
+        let value = take_value(&flag, inline_value, &mut args)?;
+        let names: Vec<String> = value
+          .split(',')
+          .map(|name| name.trim().to_string())
+          .collect();
+        if names
+          .iter()
+          .any(|name| name.is_empty() || name.contains('/'))
+        {
+          return Err(ParseArgsError::invalid(
+            &flag,
+            &value,
+            "expected a list of file names",
+          ));
+        }
+        options.index = Some(names);
+      }
+      _ => return Err(ParseArgsError::UnknownFlag(arg)),
+    }
+  }
+
+  Ok(Action::Run(options))
+}
+
+/// Gets the value of a flag, either from the `--flag=value` form
+/// or from the next argument.
+fn take_value<I>(
+  flag: &str,
+  inline_value: Option<String>,
+  args: &mut I,
+) -> Result<String, ParseArgsError>
+where
+  I: Iterator<Item = String>,
+{
+  match inline_value.or_else(|| args.next()) {
+    Some(ref value) if value.is_empty() => Err(ParseArgsError::MissingValue(flag.to_string())),
+    Some(value) => Ok(value),
+    None => Err(ParseArgsError::MissingValue(flag.to_string())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse_options(args: &[&str]) -> Options {
+    match parse_args(args.iter().cloned()) {
+      Ok(Action::Run(options)) => options,
+      Ok(action) => panic!("Expected options, got: {:?}", action),
+      Err(e) => panic!("Should not get error on valid arguments: {}", e),
+    }
+  }
+
+  #[test]
+  fn no_args_gives_no_options() {
+    assert_eq!(Options::default(), parse_options(&[]));
+  }
+
+  #[test]
+  fn parse_all_flags() {
+    let options = parse_options(&[
+      "--port",
+      "3000",
+      "--bind=127.0.0.1",
+      "-t",
+      "8",
+      "--root",
+      "./html",
+      "--config",
+      "server.toml",
+      "--log-format",
+      "json",
+      "--log-level=debug",
+      "--index",
+      "index.html,default.html",
+      "--dev",
+      "--check-config",
+    ]);
+
+    let expected = Options {
+      port: Some(3000),
+      bind: Some("127.0.0.1".to_string()),
+      root: Some(PathBuf::from("./html")),
+      threads: Some(8),
+      config: Some(PathBuf::from("server.toml")),
+      log_format: Some(LogFormat::Json),
+      log_level: Some(LogLevel::Debug),
+      index: Some(vec!["index.html".to_string(), "default.html".to_string()]),
+      dev: true,
+      check_config: true,
+    };
+    assert_eq!(
+      expected, options,
+      "Flags were not parsed into expected options"
+    );
+  }
+
+  #[test]
+  fn help_and_version() {
+    assert_eq!(Ok(Action::Help), parse_args(vec!["--port", "80", "-h"]));
+    assert_eq!(Ok(Action::Version), parse_args(vec!["--version"]));
+  }
+
+  #[test]
+  fn invalid_args() {
+    let invalid = vec![
+      vec!["--port", "http"],
+      vec!["--port", "70000"],
+      vec!["--threads", "0"],
+      vec!["--bind", "not an address"],
+      vec!["--log-format", "xml"],
+      vec!["--log-level", "verbose"],
+      vec!["--root", "./does/not/exist"],
+      vec!["--index", "a/index.html"],
+      vec!["--port"],
+      vec!["--port="],
+      vec!["--verbose"],
+    ];
+
+    for args in invalid {
+      if parse_args(args.clone()).is_ok() {
+        panic!("Should get error on invalid arguments: {:?}", args);
+      }
+    }
+  }
+}
@@ -0,0 +1,1357 @@
+use std::{collections::HashMap, fmt::{self, Display}, fs::File, io::Read, str::FromStr};
+use std::{net::{IpAddr, SocketAddr, ToSocketAddrs}, path::{Path, PathBuf}, time::Duration};
+
+use http::StatusCode;
+
+use super::cli::Options;
+use super::errors::ConfigError;
+use super::middleware;
+use super::toml::{self, Kind, Table, Value};
+use super::utils;
+
+/// The complete configuration of the server. It is either
+/// loaded from a file given with `--config`, or built from
+/// the command-line options alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+  /// Addresses which are listened on by sites that do not
+  /// have their own list of addresses.
+  pub listen: Vec<String>,
+  pub threads: usize,
+  pub dev: bool,
+  pub logging: LoggingConfig,
+  pub limits: Limits,
+  pub timeouts: Timeouts,
+  pub cache: CacheConfig,
+  /// Where the admin pages are served, if they are enabled.
+  pub admin: Option<AdminConfig>,
+  pub sites: Vec<SiteConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggingConfig {
+  /// File to write the access log to, `None` means stdout.
+  pub access_log: Option<PathBuf>,
+  pub format: LogFormat,
+  /// The least severe level of the messages which are logged.
+  pub level: LogLevel,
+}
+
+/// The admin pages, such as the metrics, are served under the
+/// path, either on a listener of their own or on every site. They
+/// are only served on the sites when every site requires
+/// authentication for the path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminConfig {
+  pub listen: Option<String>,
+  pub path: String,
+}
+
+impl Default for AdminConfig {
+  fn default() -> Self {
+    AdminConfig {
+      listen: None,
+      path: "/_admin".to_string(),
+    }
+  }
+}
+
+/// Limits on the size of requests which are accepted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+  pub max_uri_length: usize,
+  pub max_header_size: usize,
+  pub max_headers: usize,
+  pub max_body_size: usize,
+}
+
+/// How long a connection may take in each part of a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+  /// Time to receive the head of a request, counted from
+  /// the first byte of the request.
+  pub head: Duration,
+  /// Time to wait for each read of the body of a request.
+  pub body: Duration,
+  /// Time an open connection may wait for the next request.
+  pub idle: Duration,
+  /// Time to send a response.
+  pub write: Duration,
+  /// Lowest average rate in bytes per second a request may be
+  /// received with, over the time spent waiting for it. Zero
+  /// turns the check off.
+  pub min_rate: usize,
+  /// Time open connections get to finish once the server
+  /// has been asked to stop.
+  pub shutdown: Duration,
+}
+
+/// How much of the files which are served is kept in memory.
+/// The cache is shared by every site.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheConfig {
+  /// Total size in bytes of the files in the cache.
+  pub max_size: usize,
+  /// Size in bytes of the largest file which is cached. Larger
+  /// files are read from disk for every request.
+  pub max_file_size: usize,
+}
+
+/// A document root and the settings used when serving it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteConfig {
+  pub listen: Vec<String>,
+  /// Host names the site answers to, such as `example.com` or
+  /// `*.example.com`. A site without host names is the default
+  /// site of the addresses it listens on.
+  pub hosts: Vec<String>,
+  pub root: PathBuf,
+  /// The files which serve a directory, the first one
+  /// which is in the directory is used.
+  pub index: Vec<String>,
+  /// The status of the redirects between paths with and without
+  /// a trailing `/`, such as from `/about` to `/about/`.
+  pub slash_redirect: StatusCode,
+  /// Maps a file extension to the mime type which is sent
+  /// for files with that extension.
+  pub mime: HashMap<String, String>,
+  /// Headers which are added to every response.
+  pub headers: Vec<(String, String)>,
+  /// Maps an error status to the document, relative to the root,
+  /// which is sent for it. Errors without one are sent the
+  /// document named after the status in the root, such as
+  /// `404.html`, if there is one.
+  pub error_pages: HashMap<u16, PathBuf>,
+  pub redirects: Vec<Redirect>,
+  pub auth: Vec<AuthRule>,
+  pub symlinks: SymlinkPolicy,
+  pub dotfiles: DotfilePolicy,
+  /// Directories, such as `/downloads/`, whose contents are listed
+  /// along with every directory below them which has no index file.
+  pub autoindex: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+  pub from: String,
+  pub to: String,
+  pub status: StatusCode,
+}
+
+/// Requires basic authentication for every path under
+/// `prefix`, such as `/admin` and `/admin/users` for `/admin`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthRule {
+  pub prefix: String,
+  pub realm: String,
+  pub users: Vec<User>,
+}
+
+/// A user of an auth rule, whose password is kept as the
+/// SHA-256 hash of the salt followed by the password.
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+  pub name: String,
+  pub salt: String,
+  pub hash: [u8; 32],
+}
+
+impl User {
+  pub fn new(name: &str, salt: &str, password: &str) -> User {
+    User {
+      name: name.to_string(),
+      salt: salt.to_string(),
+      hash: utils::sha256(format!("{}{}", salt, password).as_bytes()),
+    }
+  }
+
+  /// Whether the password is the password of the user, which
+  /// is found out in constant time.
+  pub fn has_password(&self, password: &str) -> bool {
+    let hash = utils::sha256(format!("{}{}", self.salt, password).as_bytes());
+    utils::constant_time_eq(&self.hash, &hash)
+  }
+}
+
+/// Whether files are served through symbolic links.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SymlinkPolicy {
+  /// Links are followed wherever they lead.
+  Follow,
+  /// Links are followed as long as they lead inside the root.
+  InsideRoot,
+  /// Nothing is served through a link.
+  Never,
+}
+
+impl FromStr for SymlinkPolicy {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "follow" => Ok(SymlinkPolicy::Follow),
+      "inside_root" => Ok(SymlinkPolicy::InsideRoot),
+      "never" => Ok(SymlinkPolicy::Never),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Whether files and directories whose names start with
+/// a dot, such as `.git` or `.env`, are served.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DotfilePolicy {
+  Allow,
+  /// They are answered as if they did not exist.
+  Deny,
+}
+
+impl FromStr for DotfilePolicy {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "allow" => Ok(DotfilePolicy::Allow),
+      "deny" => Ok(DotfilePolicy::Deny),
+      _ => Err(()),
+    }
+  }
+}
+
+/// The format used when writing the access log.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LogFormat {
+  Common,
+  Combined,
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "common" | "clf" => Ok(LogFormat::Common),
+      "combined" => Ok(LogFormat::Combined),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(()),
+    }
+  }
+}
+
+impl Display for LogFormat {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+    let format_str = match *self {
+      LogFormat::Common => "common",
+      LogFormat::Combined => "combined",
+      LogFormat::Json => "json",
+    };
+    write!(fmt, "{}", format_str)
+  }
+}
+
+/// The severity of a log message, from the most to the least severe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+impl LogLevel {
+  /// All levels ordered from the most to the least severe.
+  pub const ALL: [LogLevel; 5] = [
+    LogLevel::Error,
+    LogLevel::Warn,
+    LogLevel::Info,
+    LogLevel::Debug,
+    LogLevel::Trace,
+  ];
+}
+
+impl FromStr for LogLevel {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "error" => Ok(LogLevel::Error),
+      "warn" => Ok(LogLevel::Warn),
+      "info" => Ok(LogLevel::Info),
+      "debug" => Ok(LogLevel::Debug),
+      "trace" => Ok(LogLevel::Trace),
+      _ => Err(()),
+    }
+  }
+}
+
+impl Display for LogLevel {
+  fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+    let level_str = match *self {
+      LogLevel::Error => "error",
+      LogLevel::Warn => "warn",
+      LogLevel::Info => "info",
+      LogLevel::Debug => "debug",
+      LogLevel::Trace => "trace",
+    };
+    write!(fmt, "{}", level_str)
+  }
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      listen: vec!["localhost:8080".to_string()],
+      threads: 4,
+      dev: false,
+      logging: LoggingConfig {
+        access_log: None,
+        format: LogFormat::Common,
+        level: LogLevel::Info,
+      },
+      limits: Limits::default(),
+      timeouts: Timeouts::default(),
+      cache: CacheConfig::default(),
+      admin: None,
+      sites: vec![SiteConfig::new("./html/")],
+    }
+  }
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Limits {
+      max_uri_length: 4 * 1024,
+      max_header_size: 8 * 1024,
+      max_headers: 100,
+      max_body_size: 1024 * 1024,
+    }
+  }
+}
+
+impl Default for Timeouts {
+  fn default() -> Self {
+    Timeouts {
+      head: Duration::from_secs(10),
+      body: Duration::from_secs(30),
+      idle: Duration::from_secs(5),
+      write: Duration::from_secs(30),
+      min_rate: 128,
+      shutdown: Duration::from_secs(10),
+    }
+  }
+}
+
+impl Default for CacheConfig {
+  fn default() -> Self {
+    CacheConfig {
+      max_size: 64 * 1024 * 1024,
+      max_file_size: 1024 * 1024,
+    }
+  }
+}
+
+impl SiteConfig {
+  pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+    SiteConfig {
+      listen: Vec::new(),
+      hosts: Vec::new(),
+      root: root.into(),
+      index: vec![
+        "index.html".to_string(),
+        "index.htm".to_string(),
+        "default.html".to_string(),
+      ],
+      slash_redirect: StatusCode::MovedPermanently,
+      mime: HashMap::new(),
+      headers: Vec::new(),
+      error_pages: HashMap::new(),
+      redirects: Vec::new(),
+      auth: Vec::new(),
+      symlinks: SymlinkPolicy::InsideRoot,
+      dotfiles: DotfilePolicy::Deny,
+      autoindex: Vec::new(),
+    }
+  }
+}
+
+impl Config {
+  /// Builds the configuration from the command-line options. When
+  /// a configuration file is given it is loaded first, and the
+  /// options given on the command-line take precedence over it.
+  pub fn from_options(options: &Options) -> Result<Config, ConfigError> {
+    let mut config = match options.config {
+      Some(ref path) => Config::load(path)?,
+      None => Config::default(),
+    };
+
+    if options.port.is_some() || options.bind.is_some() {
+      let bind = options
+        .bind
+        .clone()
+        .unwrap_or_else(|| "localhost".to_string());
+      let port = options.port.unwrap_or(8080);
+      // An IPv6 address is written in brackets, such as `[::1]:8080`
+      let address = match bind.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{}:{}", bind, port),
+      };
+      config.listen = vec![address];
+    }
+    if let Some(threads) = options.threads {
+      config.threads = threads;
+    }
+    if let Some(format) = options.log_format {
+      config.logging.format = format;
+    }
+    if let Some(level) = options.log_level {
+      config.logging.level = level;
+    }
+    config.dev |= options.dev;
+
+    if options.root.is_some() || options.index.is_some() {
+      if config.sites.len() > 1 {
+        return Err(ConfigError::invalid_option(
+          "--root and --index cannot be used with a configuration of several sites",
+        ));
+      }
+      let site = &mut config.sites[0];
+      if let Some(ref root) = options.root {
+        site.root = root.clone();
+      }
+      if let Some(ref index) = options.index {
+        site.index = index.clone();
+      }
+    }
+
+    config.validate_listeners()?;
+    Ok(config)
+  }
+
+  /// Reads and validates a configuration file.
+  pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Config::parse(&contents)
+  }
+
+  /// Parses and validates the contents of a configuration file.
+  pub fn parse(contents: &str) -> Result<Config, ConfigError> {
+    let root = toml::parse(contents)?;
+    let mut config = Config::default();
+    let mut sites = Vec::new();
+
+    for &(ref key, ref value) in root.entries() {
+      match key.as_str() {
+        "server" => parse_server(&mut config, table(value, key)?)?,
+        "logging" => parse_logging(&mut config.logging, table(value, key)?)?,
+        "limits" => parse_limits(&mut config.limits, table(value, key)?)?,
+        "timeouts" => parse_timeouts(&mut config.timeouts, table(value, key)?)?,
+        "cache" => parse_cache(&mut config.cache, table(value, key)?)?,
+        "admin" => config.admin = Some(parse_admin(table(value, key)?)?),
+        "site" => {
+          for site in tables(value, key)? {
+            sites.push(parse_site(site)?);
+          }
+        }
+        _ => return Err(unknown_key(value, key, None)),
+      }
+    }
+
+    if !sites.is_empty() {
+      config.sites = sites;
+    }
+    config.validate_listeners()?;
+    config.validate_admin()?;
+    Ok(config)
+  }
+
+  /// Checks what the configuration refers to outside of itself: that
+  /// every document root is a directory and every address resolves.
+  /// This is done when starting rather than when parsing, so that
+  /// parsing does not depend on the machine it runs on.
+  pub fn check_environment(&self) -> Result<(), ConfigError> {
+    if let Some(site) = self.sites.iter().find(|site| !site.root.is_dir()) {
+      return Err(ConfigError::invalid_option(format!(
+        "document root {} is not a directory",
+        site.root.display()
+      )));
+    }
+    let admin = self.admin.as_ref().and_then(|admin| admin.listen.clone());
+    let addresses = self
+      .listeners()
+      .into_iter()
+      .map(|(address, _)| address)
+      .chain(admin);
+    for address in addresses {
+      if address.to_socket_addrs().is_err() {
+        return Err(ConfigError::invalid_option(format!(
+          "unable to resolve address {}",
+          address
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// Groups the sites by the addresses they listen on. Every
+  /// address is given with the indices of the sites served there.
+  pub fn listeners(&self) -> Vec<(String, Vec<usize>)> {
+    let mut listeners: Vec<(String, Vec<usize>)> = Vec::new();
+    for (i, site) in self.sites.iter().enumerate() {
+      let addresses = if site.listen.is_empty() {
+        &self.listen
+      } else {
+        &site.listen
+      };
+      for address in addresses {
+        match listeners.iter().position(|&(ref a, _)| a == address) {
+          Some(pos) => listeners[pos].1.push(i),
+          None => listeners.push((address.clone(), vec![i])),
+        }
+      }
+    }
+    listeners
+  }
+
+  /// Sites which share an address are told apart by their host
+  /// names, so every host name can only be used once for each
+  /// address and there can only be one default site.
+  fn validate_listeners(&self) -> Result<(), ConfigError> {
+    let listeners = self.listeners();
+    if let Some(AdminConfig {
+      listen: Some(ref admin),
+      ..
+    }) = self.admin
+    {
+      if listeners.iter().any(|&(ref address, _)| address == admin) {
+        return Err(ConfigError::invalid_option(format!(
+          "the admin pages and a site both listen on {}",
+          admin
+        )));
+      }
+    }
+
+    for (address, sites) in listeners {
+      let mut hosts: Vec<&str> = Vec::new();
+      let mut has_default = false;
+
+      for &i in &sites {
+        let site = &self.sites[i];
+        if site.hosts.is_empty() {
+          if has_default {
+            return Err(ConfigError::invalid_option(format!(
+              "several sites without `hosts` listen on {}",
+              address
+            )));
+          }
+          has_default = true;
+        }
+        for host in &site.hosts {
+          if hosts.contains(&host.as_str()) {
+            return Err(ConfigError::invalid_option(format!(
+              "host {} is used by several sites on {}",
+              host, address
+            )));
+          }
+          hosts.push(host);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// The admin pages tell about the whole server, so without a
+  /// listener of their own every site must protect them.
+  fn validate_admin(&self) -> Result<(), ConfigError> {
+    let path = match self.admin {
+      Some(AdminConfig {
+        listen: None,
+        ref path,
+      }) => format!("{}/", path),
+      _ => return Ok(()),
+    };
+    let is_protected = |site: &SiteConfig| {
+      site
+        .auth
+        .iter()
+        .any(|rule| middleware::has_prefix(&path, &rule.prefix))
+    };
+    if !self.sites.iter().all(is_protected) {
+      return Err(ConfigError::invalid_option(format!(
+        "[admin] needs `listen`, or an auth rule for {} on every site",
+        path
+      )));
+    }
+    Ok(())
+  }
+}
+
+fn parse_server(config: &mut Config, server: &Table) -> Result<(), ConfigError> {
+  for &(ref key, ref value) in server.entries() {
+    match key.as_str() {
+      "listen" => config.listen = addresses(value, key)?,
+      "threads" => config.threads = positive(value, key)?,
+      "dev" => config.dev = boolean(value, key)?,
+      _ => return Err(unknown_key(value, key, Some("server"))),
+    }
+  }
+  Ok(())
+}
+
+fn parse_logging(logging: &mut LoggingConfig, table: &Table) -> Result<(), ConfigError> {
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "access_log" => {
+        let path = string(value, key)?;
+        logging.access_log = if path == "-" {
+          None
+        } else {
+          Some(PathBuf::from(path))
+        };
+      }
+      "format" => {
+        logging.format = LogFormat::from_str(&string(value, key)?).map_err(|_| {
+          ConfigError::invalid(
+            value.line,
+            "`format` must be one of common, combined or json",
+          )
+        })?;
+      }
+      "level" => {
+        logging.level = LogLevel::from_str(&string(value, key)?).map_err(|_| {
+          ConfigError::invalid(
+            value.line,
+            "`level` must be one of error, warn, info, debug or trace",
+          )
+        })?;
+      }
+      _ => return Err(unknown_key(value, key, Some("logging"))),
+    }
+  }
+  Ok(())
+}
+
+fn parse_limits(limits: &mut Limits, table: &Table) -> Result<(), ConfigError> {
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "max_uri_length" => limits.max_uri_length = positive(value, key)?,
+      "max_header_size" => limits.max_header_size = positive(value, key)?,
+      "max_headers" => limits.max_headers = positive(value, key)?,
+      "max_body_size" => limits.max_body_size = positive(value, key)?,
+      _ => return Err(unknown_key(value, key, Some("limits"))),
+    }
+  }
+  Ok(())
+}
+
+fn parse_cache(cache: &mut CacheConfig, table: &Table) -> Result<(), ConfigError> {
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "max_size" => cache.max_size = positive(value, key)?,
+      "max_file_size" => cache.max_file_size = positive(value, key)?,
+      _ => return Err(unknown_key(value, key, Some("cache"))),
+    }
+  }
+  Ok(())
+}
+
+fn parse_admin(table: &Table) -> Result<AdminConfig, ConfigError> {
+  let mut admin = AdminConfig::default();
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "listen" => {
+        let address = string(value, key)?;
+        if !is_valid_address(&address) {
+          return Err(ConfigError::invalid(
+            value.line,
+            format!("`{}` is not a valid address, expected host:port", address),
+          ));
+        }
+        admin.listen = Some(address);
+      }
+      "path" => {
+        admin.path = uri_path(value, key)?.trim_right_matches('/').to_string();
+        if admin.path.is_empty() {
+          return Err(ConfigError::invalid(
+            value.line,
+            "`path` cannot be the root",
+          ));
+        }
+      }
+      _ => return Err(unknown_key(value, key, Some("admin"))),
+    }
+  }
+  Ok(admin)
+}
+
+fn parse_timeouts(timeouts: &mut Timeouts, table: &Table) -> Result<(), ConfigError> {
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "head" => timeouts.head = seconds(value, key)?,
+      "body" => timeouts.body = seconds(value, key)?,
+      "idle" => timeouts.idle = seconds(value, key)?,
+      "write" => timeouts.write = seconds(value, key)?,
+      "shutdown" => timeouts.shutdown = seconds(value, key)?,
+      "min_rate" => {
+        timeouts.min_rate = match integer(value, key)? {
+          i if i >= 0 => i as usize,
+          _ => {
+            return Err(ConfigError::invalid(
+              value.line,
+              "`min_rate` cannot be negative",
+            ))
+          }
+        }
+      }
+      _ => return Err(unknown_key(value, key, Some("timeouts"))),
+    }
+  }
+  Ok(())
+}
+
+fn parse_site(value: &Value) -> Result<SiteConfig, ConfigError> {
+  let table = table(value, "site")?;
+  let mut site = match table.get("root") {
+    Some(root) => SiteConfig::new(string(root, "root")?),
+    None => return Err(ConfigError::invalid(value.line, "site is missing `root`")),
+  };
+
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "root" => {}
+      "listen" => site.listen = addresses(value, key)?,
+      "hosts" => {
+        site.hosts = strings(value, key)?
+          .into_iter()
+          .map(|host| host.to_lowercase())
+          .collect();
+        if let Some(host) = site.hosts.iter().find(|host| !is_valid_host(host)) {
+          return Err(ConfigError::invalid(
+            value.line,
+            format!("`{}` is not a valid host name", host),
+          ));
+        }
+      }
+      "index" => {
+        site.index = strings(value, key)?;
+        let is_file_name = |name: &String| {
+          !name.is_empty()
+            && name != "."
+            && name != ".."
+            && !name.contains(|c| c == '/' || c == '\\')
+        };
+        if !site.index.iter().all(is_file_name) {
+          return Err(ConfigError::invalid(
+            value.line,
+            "`index` must be a list of file names",
+          ));
+        }
+      }
+      "slash_redirect" => {
+        site.slash_redirect = match integer(value, key)? {
+          301 => StatusCode::MovedPermanently,
+          308 => StatusCode::PermanentRedirect,
+          _ => {
+            return Err(ConfigError::invalid(
+              value.line,
+              "`slash_redirect` must be 301 or 308",
+            ))
+          }
+        }
+      }
+      "mime" => {
+        for &(ref ext, ref mime) in self::table(value, key)?.entries() {
+          site
+            .mime
+            .insert(ext.trim_left_matches('.').to_string(), string(mime, ext)?);
+        }
+      }
+      "headers" => {
+        for &(ref name, ref header_value) in self::table(value, key)?.entries() {
+          if name.is_empty() || name.contains(|c: char| c == ':' || c.is_whitespace()) {
+            return Err(ConfigError::invalid(
+              header_value.line,
+              format!("invalid header name `{}`", name),
+            ));
+          }
+          site
+            .headers
+            .push((name.clone(), string(header_value, name)?));
+        }
+      }
+      "error_pages" => {
+        for &(ref status, ref path) in self::table(value, key)?.entries() {
+          let status_code = match status.parse::<u16>() {
+            Ok(status_code) if status_code >= 400 && status_code < 600 => status_code,
+            _ => {
+              return Err(ConfigError::invalid(
+                path.line,
+                format!("`{}` is not an error status such as 404", status),
+              ))
+            }
+          };
+          site
+            .error_pages
+            .insert(status_code, PathBuf::from(string(path, status)?));
+        }
+      }
+      "redirect" => {
+        for redirect in tables(value, key)? {
+          site.redirects.push(parse_redirect(redirect)?);
+        }
+      }
+      "auth" => {
+        for rule in tables(value, key)? {
+          site.auth.push(parse_auth(rule)?);
+        }
+      }
+      "symlinks" => {
+        site.symlinks = SymlinkPolicy::from_str(&string(value, key)?).map_err(|_| {
+          ConfigError::invalid(
+            value.line,
+            "`symlinks` must be one of follow, inside_root or never",
+          )
+        })?;
+      }
+      "autoindex" => {
+        site.autoindex = strings(value, key)?;
+        if site
+          .autoindex
+          .iter()
+          .any(|dir| !dir.starts_with('/') || !dir.ends_with('/'))
+        {
+          return Err(ConfigError::invalid(
+            value.line,
+            "`autoindex` must be a list of directories such as \"/downloads/\"",
+          ));
+        }
+      }
+      "dotfiles" => {
+        site.dotfiles = DotfilePolicy::from_str(&string(value, key)?).map_err(|_| {
+          ConfigError::invalid(value.line, "`dotfiles` must be one of allow or deny")
+        })?;
+      }
+      _ => return Err(unknown_key(value, key, Some("site"))),
+    }
+  }
+  Ok(site)
+}
+
+fn parse_redirect(value: &Value) -> Result<Redirect, ConfigError> {
+  let table = table(value, "redirect")?;
+  let mut from = None;
+  let mut to = None;
+  let mut status = StatusCode::MovedPermanently;
+
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "from" => from = Some(uri_path(value, key)?),
+      "to" => to = Some(string(value, key)?),
+      "status" => {
+        status = match integer(value, key)? {
+          301 => StatusCode::MovedPermanently,
+          302 => StatusCode::Found,
+          303 => StatusCode::SeeOther,
+          307 => StatusCode::TemporaryRedirect,
+          308 => StatusCode::PermanentRedirect,
+          _ => {
+            return Err(ConfigError::invalid(
+              value.line,
+              "`status` must be one of 301, 302, 303, 307 or 308",
+            ))
+          }
+        }
+      }
+      _ => return Err(unknown_key(value, key, Some("site.redirect"))),
+    }
+  }
+
+  match (from, to) {
+    (Some(from), Some(to)) => Ok(Redirect { from, to, status }),
+    _ => Err(ConfigError::invalid(
+      value.line,
+      "redirect needs both `from` and `to`",
+    )),
+  }
+}
+
+fn parse_auth(value: &Value) -> Result<AuthRule, ConfigError> {
+  let table = table(value, "auth")?;
+  let mut prefix = None;
+  let mut realm = "Restricted".to_string();
+  let mut users = Vec::new();
+
+  for &(ref key, ref value) in table.entries() {
+    match key.as_str() {
+      "prefix" => prefix = Some(uri_path(value, key)?),
+      "realm" => {
+        realm = string(value, key)?;
+        if realm.contains('"') {
+          return Err(ConfigError::invalid(
+            value.line,
+            "`realm` cannot contain `\"`",
+          ));
+        }
+      }
+      "users" => {
+        for user in strings(value, key)? {
+          let mut parts = user.splitn(2, ':');
+          match (parts.next(), parts.next()) {
+            (Some(name), Some(password)) if !name.is_empty() => {
+              users.push(parse_user(name, password, value.line)?)
+            }
+            _ => {
+              return Err(ConfigError::invalid(
+                value.line,
+                concat!(
+                  "`users` must be a list of \"name:sha256:salt:hash\", ",
+                  "or of \"name:password\" for development only"
+                ),
+              ))
+            }
+          }
+        }
+      }
+      _ => return Err(unknown_key(value, key, Some("site.auth"))),
+    }
+  }
+
+  match prefix {
+    Some(prefix) => Ok(AuthRule {
+      prefix,
+      realm,
+      users,
+    }),
+    None => Err(ConfigError::invalid(
+      value.line,
+      "auth rule is missing `prefix`",
+    )),
+  }
+}
+
+fn unknown_key(value: &Value, key: &str, table: Option<&str>) -> ConfigError {
+  match table {
+    Some(table) => {
+      ConfigError::invalid(value.line, format!("unknown key `{}` in [{}]", key, table))
+    }
+    None => ConfigError::invalid(value.line, format!("unknown key `{}`", key)),
+  }
+}
+
+fn type_error(value: &Value, key: &str, expected: &str) -> ConfigError {
+  ConfigError::invalid(
+    value.line,
+    format!(
+      "expected {} for `{}`, found {}",
+      expected,
+      key,
+      value.kind.type_name()
+    ),
+  )
+}
+
+fn string(value: &Value, key: &str) -> Result<String, ConfigError> {
+  match value.kind {
+    Kind::String(ref s) => Ok(s.clone()),
+    _ => Err(type_error(value, key, "a string")),
+  }
+}
+
+fn integer(value: &Value, key: &str) -> Result<i64, ConfigError> {
+  match value.kind {
+    Kind::Integer(i) => Ok(i),
+    _ => Err(type_error(value, key, "an integer")),
+  }
+}
+
+fn positive(value: &Value, key: &str) -> Result<usize, ConfigError> {
+  match integer(value, key)? {
+    i if i > 0 => Ok(i as usize),
+    _ => Err(ConfigError::invalid(
+      value.line,
+      format!("`{}` must be a positive integer", key),
+    )),
+  }
+}
+
+fn seconds(value: &Value, key: &str) -> Result<Duration, ConfigError> {
+  positive(value, key).map(|secs| Duration::from_secs(secs as u64))
+}
+
+fn boolean(value: &Value, key: &str) -> Result<bool, ConfigError> {
+  match value.kind {
+    Kind::Boolean(b) => Ok(b),
+    _ => Err(type_error(value, key, "a boolean")),
+  }
+}
+
+fn table<'v>(value: &'v Value, key: &str) -> Result<&'v Table, ConfigError> {
+  match value.kind {
+    Kind::Table(ref table) => Ok(table),
+    _ => Err(type_error(value, key, "a table")),
+  }
+}
+
+fn array<'v>(value: &'v Value, key: &str) -> Result<&'v [Value], ConfigError> {
+  match value.kind {
+    Kind::Array(ref values) => Ok(values),
+    _ => Err(type_error(value, key, "an array")),
+  }
+}
+
+fn tables<'v>(value: &'v Value, key: &str) -> Result<&'v [Value], ConfigError> {
+  let values = array(value, key)?;
+  for value in values {
+    table(value, key)?;
+  }
+  Ok(values)
+}
+
+fn strings(value: &Value, key: &str) -> Result<Vec<String>, ConfigError> {
+  array(value, key)?.iter().map(|v| string(v, key)).collect()
+}
+
+fn uri_path(value: &Value, key: &str) -> Result<String, ConfigError> {
+  let path = string(value, key)?;
+  if !path.starts_with('/') {
+    return Err(ConfigError::invalid(
+      value.line,
+      format!("`{}` must start with `/`", key),
+    ));
+  }
+  Ok(path)
+}
+
+/// A host name is a list of labels separated by dots, where
+/// the first label can be the wildcard `*`.
+fn is_valid_host(host: &str) -> bool {
+  let host = if host.starts_with("*.") {
+    &host[2..]
+  } else {
+    host
+  };
+  !host.is_empty()
+    && host.split('.').all(|label| {
+      !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// The password of a user is either given as the SHA-256 hash of
+/// the salt followed by the password, as `sha256:<salt>:<hex>`, or
+/// in plaintext, which is only meant for development.
+fn parse_user(name: &str, password: &str, line: usize) -> Result<User, ConfigError> {
+  if !password.starts_with("sha256:") {
+    return Ok(User::new(name, "", password));
+  }
+  let invalid = || {
+    ConfigError::invalid(
+      line,
+      "a `sha256:` password must be a salt and 64 hex digits, as salt:hex",
+    )
+  };
+  let mut parts = password["sha256:".len()..].splitn(2, ':');
+  let (salt, hex) = match (parts.next(), parts.next()) {
+    (Some(salt), Some(hex)) if !salt.is_empty() => (salt, hex),
+    _ => return Err(invalid()),
+  };
+  if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    return Err(invalid());
+  }
+  let mut hash = [0u8; 32];
+  for (i, byte) in hash.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+  }
+  Ok(User {
+    name: name.to_string(),
+    salt: salt.to_string(),
+    hash,
+  })
+}
+
+/// Whether the address looks like `host:port`. The host is only
+/// resolved when the server starts, see `Config::check_environment`.
+fn is_valid_address(address: &str) -> bool {
+  let mut parts = address.rsplitn(2, ':');
+  match (parts.next(), parts.next()) {
+    (Some(port), Some(host)) => {
+      let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+      } else {
+        host
+      };
+      port.parse::<u16>().is_ok()
+        && !host.is_empty()
+        && !host.contains(|c: char| c.is_whitespace() || c == '[' || c == ']')
+    }
+    _ => false,
+  }
+}
+
+fn addresses(value: &Value, key: &str) -> Result<Vec<String>, ConfigError> {
+  let addresses = strings(value, key)?;
+  if addresses.is_empty() {
+    return Err(ConfigError::invalid(
+      value.line,
+      format!("`{}` cannot be empty", key),
+    ));
+  }
+  for address in &addresses {
+    if !is_valid_address(address) {
+      return Err(ConfigError::invalid(
+        value.line,
+        format!("`{}` is not a valid address, expected host:port", address),
+      ));
+    }
+  }
+  Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_full_config() {
+    let config_str = r#"
+[server]
+listen = ["127.0.0.1:8080"]
+threads = 2
+
+[logging]
+access_log = "access.log"
+format = "combined"
+level = "debug"
+
+[limits]
+max_body_size = 4096
+
+[timeouts]
+idle = 60
+shutdown = 2
+
+[cache]
+max_file_size = 65536
+
+[admin]
+listen = "127.0.0.1:9090"
+
+[[site]]
+root = "./html"
+index = ["index.html", "index.htm"]
+slash_redirect = 308
+symlinks = "never"
+dotfiles = "allow"
+autoindex = ["/about/"]
+
+[site.mime]
+md = "text/markdown"
+
+[site.headers]
+"X-Frame-Options" = "DENY"
+
+[site.error_pages]
+404 = "errors/not-found.html"
+
+[[site.redirect]]
+from = "/old/"
+to = "/about/"
+status = 302
+
+[[site.auth]]
+prefix = "/about/us/"
+users = [
+  "admin:secret",
+  "editor:sha256:pepper:744a9101f7182a6ae0d978121ff74e33cac8d2832579c0637c1c37e9bbb6c065",
+]
+"#;
+    let config = match Config::parse(config_str) {
+      Ok(config) => config,
+      Err(e) => panic!("Should not get error on valid config: {}", e),
+    };
+
+    let mut expected_site = SiteConfig::new("./html");
+    expected_site.index = vec!["index.html".to_string(), "index.htm".to_string()];
+    expected_site.slash_redirect = StatusCode::PermanentRedirect;
+    expected_site.symlinks = SymlinkPolicy::Never;
+    expected_site.dotfiles = DotfilePolicy::Allow;
+    expected_site.autoindex = vec!["/about/".to_string()];
+    expected_site
+      .mime
+      .insert("md".to_string(), "text/markdown".to_string());
+    expected_site
+      .headers
+      .push(("X-Frame-Options".to_string(), "DENY".to_string()));
+    expected_site
+      .error_pages
+      .insert(404, PathBuf::from("errors/not-found.html"));
+    expected_site.redirects.push(Redirect {
+      from: "/old/".to_string(),
+      to: "/about/".to_string(),
+      status: StatusCode::Found,
+    });
+    expected_site.auth.push(AuthRule {
+      prefix: "/about/us/".to_string(),
+      realm: "Restricted".to_string(),
+      users: vec![
+        User::new("admin", "", "secret"),
+        User::new("editor", "pepper", "secret"),
+      ],
+    });
+
+    let expected = Config {
+      listen: vec!["127.0.0.1:8080".to_string()],
+      threads: 2,
+      dev: false,
+      logging: LoggingConfig {
+        access_log: Some(PathBuf::from("access.log")),
+        format: LogFormat::Combined,
+        level: LogLevel::Debug,
+      },
+      limits: Limits {
+        max_body_size: 4096,
+        ..Limits::default()
+      },
+      timeouts: Timeouts {
+        idle: Duration::from_secs(60),
+        shutdown: Duration::from_secs(2),
+        ..Timeouts::default()
+      },
+      cache: CacheConfig {
+        max_file_size: 65536,
+        ..CacheConfig::default()
+      },
+      admin: Some(AdminConfig {
+        listen: Some("127.0.0.1:9090".to_string()),
+        path: "/_admin".to_string(),
+      }),
+      sites: vec![expected_site],
+    };
+    assert_eq!(
+      expected, config,
+      "Config was not parsed into expected structure"
+    );
+  }
+
+  #[test]
+  fn invalid_config_reports_line() {
+    let invalid = vec![
+      ("[server]\nthreads = 0\n", 2),
+      ("[server]\nthreads = \"four\"\n", 2),
+      ("[server]\nport = 80\n", 2),
+      ("[server]\nlisten = [\"localhost\"]\n", 2),
+      (
+        "[[site]]\nroot = \"./html\"\n[[site.auth]]\nprefix = \"/\"\nusers = [\"a:sha256:12\"]\n",
+        5,
+      ),
+      (
+        "[[site]]\nroot = \"./html\"\n[[site.auth]]\nprefix = \"/\"\nusers = [\"a:sha256:s:12\"]\n",
+        5,
+      ),
+      ("[[site]]\n\nindex = [\"index.html\"]\n", 1),
+      ("[[site]]\nroot = \"./html\"\n[[site.redirect]]\nfrom = \"old\"\nto = \"/\"\n", 4),
+      (
+        concat!(
+          "[[site]]\nroot = \"./html\"\n",
+          "[[site.redirect]]\nfrom = \"/old\"\nto = \"/\"\nstatus = 200\n"
+        ),
+        6,
+      ),
+      ("[logging]\nformat = \"xml\"\n", 2),
+      ("[timeouts]\n\nhead = 0\n", 3),
+      ("[[site]]\nroot = \"./html\"\nhosts = [\"a..com\"]\n", 3),
+      ("[[site]]\nroot = \"./html\"\n\nsymlinks = \"always\"\n", 4),
+      ("[[site]]\nroot = \"./html\"\nautoindex = [\"files\"]\n", 3),
+      ("[[site]]\nroot = \"./html\"\nslash_redirect = 302\n", 3),
+      ("[[site]]\nroot = \"./html\"\n[site.error_pages]\n200 = \"ok.html\"\n", 4),
+      ("[[site]]\nroot = \"./html\"\nindex = [\"..\"]\n", 3),
+    ];
+
+    for (config_str, line) in invalid {
+      match Config::parse(config_str) {
+        Ok(_) => panic!("Should get error on invalid config: {:?}", config_str),
+        Err(e) => assert_eq!(Some(line), e.line(), "Wrong line for error: {}", e),
+      }
+    }
+  }
+
+  #[test]
+  fn environment_is_checked_after_parsing() {
+    let config_str =
+      "[server]\nlisten = [\"127.0.0.1:0\"]\n[[site]]\nroot = \"./does/not/exist\"\n";
+    let config = match Config::parse(config_str) {
+      Ok(config) => config,
+      Err(e) => panic!("Should not get error on valid config: {}", e),
+    };
+    assert!(config.check_environment().is_err());
+
+    let config_str = "[server]\nlisten = [\"127.0.0.1:0\"]\n[[site]]\nroot = \"./html\"\n";
+    match Config::parse(config_str).map(|config| config.check_environment()) {
+      Ok(Ok(())) => {}
+      result => panic!("Should not get error on valid environment: {:?}", result),
+    }
+  }
+
+  #[test]
+  fn sites_on_same_address_need_hosts() {
+    let config_str = "[[site]]\nroot = \"./html\"\n\n[[site]]\nroot = \"./html/about\"\n";
+    assert!(Config::parse(config_str).is_err());
+
+    let config_str = concat!(
+      "[[site]]\nroot = \"./html\"\nhosts = [\"a.com\"]\n\n",
+      "[[site]]\nroot = \"./html/about\"\nhosts = [\"a.com\"]\n"
+    );
+    assert!(Config::parse(config_str).is_err());
+
+    let config_str =
+      "[[site]]\nroot = \"./html\"\n\n[[site]]\nroot = \"./html/about\"\nhosts = [\"*.A.com\"]\n";
+    match Config::parse(config_str) {
+      Ok(config) => assert_eq!(vec!["*.a.com".to_string()], config.sites[1].hosts),
+      Err(e) => panic!("Should not get error on valid config: {}", e),
+    }
+  }
+
+  #[test]
+  fn admin_pages_on_sites_need_auth() {
+    let config_str = "[admin]\npath = \"/status\"\n\n[[site]]\nroot = \"./html\"\n";
+    assert!(Config::parse(config_str).is_err());
+
+    let config_str = concat!(
+      "[admin]\npath = \"/status\"\n\n[[site]]\nroot = \"./html\"\n",
+      "[[site.auth]]\nprefix = \"/stat\"\nusers = [\"a:b\"]\n"
+    );
+    assert!(Config::parse(config_str).is_err());
+
+    let config_str = concat!(
+      "[admin]\npath = \"/status\"\n\n[[site]]\nroot = \"./html\"\n",
+      "[[site.auth]]\nprefix = \"/status\"\nusers = [\"a:b\"]\n"
+    );
+    if let Err(e) = Config::parse(config_str) {
+      panic!("Should not get error on valid config: {}", e);
+    }
+  }
+
+  #[test]
+  fn options_override_config() {
+    let mut options = Options::default();
+    options.port = Some(3000);
+    options.threads = Some(1);
+    options.root = Some(PathBuf::from("./html/about"));
+
+    let config = match Config::from_options(&options) {
+      Ok(config) => config,
+      Err(e) => panic!("Should not get error on valid options: {}", e),
+    };
+    assert_eq!(vec!["localhost:3000".to_string()], config.listen);
+    assert_eq!(1, config.threads);
+    assert_eq!(PathBuf::from("./html/about"), config.sites[0].root);
+
+    options.bind = Some("::1".to_string());
+    options.root = None;
+    match Config::from_options(&options) {
+      Ok(config) => assert_eq!(vec!["[::1]:3000".to_string()], config.listen),
+      Err(e) => panic!("Should not get error on valid options: {}", e),
+    }
+  }
+}
@@ -1,5 +1,5 @@
 use std::{error, fmt, io, string};
-use http::ParseHttpError;
+use http::{ParseErrorKind, ParseHttpError, StatusCode};
 
 #[derive(Debug)]
 pub enum HandleStreamError {
@@ -36,6 +36,67 @@ impl error::Error for HandleStreamError {
   }
 }
 
+impl HandleStreamError {
+  /// A short name for the kind of error, used as a metric label.
+  pub fn kind_name(&self) -> &'static str {
+    match *self {
+      HandleStreamError::Io(_) => "io",
+      HandleStreamError::ParseHttp(ref err) => match err.kind() {
+        ParseErrorKind::Invalid => "invalid",
+        ParseErrorKind::Empty => "empty",
+        ParseErrorKind::UnsupportedVersion => "unsupported_version",
+        ParseErrorKind::UnknownMethod => "unknown_method",
+      },
+      HandleStreamError::ReadStream(ref err) => match *err {
+        ReadStreamError::Io(_) => "io",
+        ReadStreamError::Parse(_) => "encoding",
+        ReadStreamError::UriTooLong => "uri_too_long",
+        ReadStreamError::HeaderTooLarge => "header_too_large",
+        ReadStreamError::TooManyHeaders => "too_many_headers",
+        ReadStreamError::BodyTooLarge => "body_too_large",
+        ReadStreamError::InvalidFraming => "invalid_framing",
+        ReadStreamError::UnsupportedCoding => "unsupported_coding",
+        ReadStreamError::UnexpectedEof => "unexpected_eof",
+        ReadStreamError::Closed => "closed",
+        ReadStreamError::Timeout(TimeoutKind::Idle) => "idle_timeout",
+        ReadStreamError::Timeout(TimeoutKind::Head) => "head_timeout",
+        ReadStreamError::Timeout(TimeoutKind::Body) => "body_timeout",
+        ReadStreamError::TooSlow => "too_slow",
+      },
+    }
+  }
+
+  /// The status of the response which should be sent to the
+  /// client because of the error, if any response can be sent.
+  pub fn status_code(&self) -> Option<StatusCode> {
+    match *self {
+      HandleStreamError::Io(_) => None,
+      HandleStreamError::ParseHttp(ref err) => Some(match err.kind() {
+        ParseErrorKind::UnsupportedVersion => StatusCode::HTTPVersionnotsupported,
+        ParseErrorKind::UnknownMethod => StatusCode::NotImplemented,
+        ParseErrorKind::Invalid | ParseErrorKind::Empty => StatusCode::BadRequest,
+      }),
+      HandleStreamError::ReadStream(ref err) => match *err {
+        ReadStreamError::Io(_) => None,
+        ReadStreamError::Parse(_) | ReadStreamError::InvalidFraming => Some(StatusCode::BadRequest),
+        ReadStreamError::UriTooLong => Some(StatusCode::RequestURITooLarge),
+        ReadStreamError::HeaderTooLarge | ReadStreamError::TooManyHeaders => {
+          Some(StatusCode::RequestHeaderFieldsTooLarge)
+        }
+        ReadStreamError::BodyTooLarge => Some(StatusCode::RequestEntityTooLarge),
+        ReadStreamError::UnsupportedCoding => Some(StatusCode::NotImplemented),
+        ReadStreamError::Timeout(TimeoutKind::Head)
+        | ReadStreamError::Timeout(TimeoutKind::Body)
+        | ReadStreamError::TooSlow => Some(StatusCode::RequestTimeout),
+        // Nothing of a request has been received, so nothing is answered
+        ReadStreamError::Timeout(TimeoutKind::Idle)
+        | ReadStreamError::UnexpectedEof
+        | ReadStreamError::Closed => None,
+      },
+    }
+  }
+}
+
 impl From<io::Error> for HandleStreamError {
   fn from(err: io::Error) -> HandleStreamError {
     HandleStreamError::Io(err)
@@ -59,6 +120,36 @@ impl From<ReadStreamError> for HandleStreamError {
 pub enum ReadStreamError {
   Io(io::Error),
   Parse(string::FromUtf8Error),
+  /// The uri in the request line is longer than allowed.
+  UriTooLong,
+  /// The head of the request is larger than allowed.
+  HeaderTooLarge,
+  /// The request has more headers than allowed.
+  TooManyHeaders,
+  /// The body of the request is larger than allowed.
+  BodyTooLarge,
+  /// The length of the body cannot be decided from the headers,
+  /// or a chunk of a chunked body is malformed.
+  InvalidFraming,
+  /// The body has a transfer coding other than `chunked`.
+  UnsupportedCoding,
+  /// The stream was closed in the middle of a request.
+  UnexpectedEof,
+  /// The stream was closed before a new request was started.
+  Closed,
+  /// A part of the request was not received in time.
+  Timeout(TimeoutKind),
+  /// The request is received slower than the minimum rate.
+  TooSlow,
+}
+
+/// The part of a connection which took too long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeoutKind {
+  /// Waiting for a new request to start.
+  Idle,
+  Head,
+  Body,
 }
 
 impl fmt::Display for ReadStreamError {
@@ -66,6 +157,7 @@ impl fmt::Display for ReadStreamError {
     match *self {
       ReadStreamError::Io(ref err) => write!(f, "IO error: {}", err),
       ReadStreamError::Parse(ref err) => write!(f, "Parse error: {}", err),
+      _ => write!(f, "{}", error::Error::description(self)),
     }
   }
 }
@@ -75,6 +167,18 @@ impl error::Error for ReadStreamError {
     match *self {
       ReadStreamError::Io(ref err) => err.description(),
       ReadStreamError::Parse(ref err) => err.description(),
+      ReadStreamError::UriTooLong => "request uri is too long",
+      ReadStreamError::HeaderTooLarge => "request header is too large",
+      ReadStreamError::TooManyHeaders => "request has too many headers",
+      ReadStreamError::BodyTooLarge => "request body is too large",
+      ReadStreamError::InvalidFraming => "request body has invalid framing",
+      ReadStreamError::UnsupportedCoding => "request body has an unsupported transfer coding",
+      ReadStreamError::UnexpectedEof => "stream closed in the middle of a request",
+      ReadStreamError::Closed => "stream closed",
+      ReadStreamError::Timeout(TimeoutKind::Idle) => "timed out waiting for a request",
+      ReadStreamError::Timeout(TimeoutKind::Head) => "timed out receiving request head",
+      ReadStreamError::Timeout(TimeoutKind::Body) => "timed out receiving request body",
+      ReadStreamError::TooSlow => "request is received too slowly",
     }
   }
 
@@ -82,6 +186,7 @@ impl error::Error for ReadStreamError {
     match *self {
       ReadStreamError::Io(ref err) => Some(err),
       ReadStreamError::Parse(ref err) => Some(err),
+      _ => None,
     }
   }
 }
@@ -97,3 +202,192 @@ impl From<string::FromUtf8Error> for ReadStreamError {
     ReadStreamError::Parse(err)
   }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+/// An error which is given when the command-line arguments are not valid.
+pub enum ParseArgsError {
+  UnknownFlag(String),
+  MissingValue(String),
+  InvalidValue {
+    flag: String,
+    value: String,
+    reason: &'static str,
+  },
+}
+
+impl ParseArgsError {
+  pub fn invalid(flag: &str, value: &str, reason: &'static str) -> Self {
+    ParseArgsError::InvalidValue {
+      flag: flag.to_string(),
+      value: value.to_string(),
+      reason,
+    }
+  }
+}
+
+impl fmt::Display for ParseArgsError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ParseArgsError::UnknownFlag(ref flag) => write!(f, "unknown option '{}'", flag),
+      ParseArgsError::MissingValue(ref flag) => write!(f, "option '{}' requires a value", flag),
+      ParseArgsError::InvalidValue {
+        ref flag,
+        ref value,
+        reason,
+      } => write!(f, "invalid value '{}' for '{}': {}", value, flag, reason),
+    }
+  }
+}
+
+impl error::Error for ParseArgsError {
+  fn description(&self) -> &str {
+    match *self {
+      ParseArgsError::UnknownFlag(_) => "unknown option",
+      ParseArgsError::MissingValue(_) => "option requires a value",
+      ParseArgsError::InvalidValue { reason, .. } => reason,
+    }
+  }
+}
+
+#[derive(Debug)]
+/// An error which is given when the configuration file
+/// cannot be read, parsed or does not validate. Errors
+/// which belong to a part of the file includes the line.
+pub enum ConfigError {
+  Io(io::Error),
+  Syntax {
+    line: usize,
+    message: String,
+  },
+  Invalid {
+    line: Option<usize>,
+    message: String,
+  },
+}
+
+impl ConfigError {
+  pub fn syntax<S: Into<String>>(line: usize, message: S) -> Self {
+    ConfigError::Syntax {
+      line,
+      message: message.into(),
+    }
+  }
+
+  pub fn invalid<S: Into<String>>(line: usize, message: S) -> Self {
+    ConfigError::Invalid {
+      line: Some(line),
+      message: message.into(),
+    }
+  }
+
+  pub fn invalid_option<S: Into<String>>(message: S) -> Self {
+    ConfigError::Invalid {
+      line: None,
+      message: message.into(),
+    }
+  }
+
+  /// The line in the configuration file which caused the error.
+  pub fn line(&self) -> Option<usize> {
+    match *self {
+      ConfigError::Io(_) => None,
+      ConfigError::Syntax { line, .. } => Some(line),
+      ConfigError::Invalid { line, .. } => line,
+    }
+  }
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ConfigError::Io(ref err) => write!(f, "IO error: {}", err),
+      ConfigError::Syntax { line, ref message } => {
+        write!(f, "line {}: syntax error: {}", line, message)
+      }
+      ConfigError::Invalid {
+        line: Some(line),
+        ref message,
+      } => write!(f, "line {}: {}", line, message),
+      ConfigError::Invalid {
+        line: None,
+        ref message,
+      } => write!(f, "{}", message),
+    }
+  }
+}
+
+impl error::Error for ConfigError {
+  fn description(&self) -> &str {
+    match *self {
+      ConfigError::Io(ref err) => err.description(),
+      ConfigError::Syntax { ref message, .. } => message,
+      ConfigError::Invalid { ref message, .. } => message,
+    }
+  }
+
+  fn cause(&self) -> Option<&error::Error> {
+    match *self {
+      ConfigError::Io(ref err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for ConfigError {
+  fn from(err: io::Error) -> ConfigError {
+    ConfigError::Io(err)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An error which is given when a route cannot be added,
+/// because its pattern is invalid or because it conflicts
+/// with a route which has already been added.
+pub enum RouteError {
+  Invalid { pattern: String, reason: String },
+  Conflict { pattern: String, reason: String },
+}
+
+impl RouteError {
+  pub fn invalid<S: Into<String>>(pattern: &str, reason: S) -> Self {
+    RouteError::Invalid {
+      pattern: pattern.to_string(),
+      reason: reason.into(),
+    }
+  }
+
+  pub fn conflict<S: Into<String>>(pattern: &str, reason: S) -> Self {
+    RouteError::Conflict {
+      pattern: pattern.to_string(),
+      reason: reason.into(),
+    }
+  }
+}
+
+impl fmt::Display for RouteError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      RouteError::Invalid {
+        ref pattern,
+        ref reason,
+      } => write!(f, "invalid route '{}': {}", pattern, reason),
+      RouteError::Conflict {
+        ref pattern,
+        ref reason,
+      } => write!(
+        f,
+        "route '{}' conflicts with an earlier route: {}",
+        pattern, reason
+      ),
+    }
+  }
+}
+
+impl error::Error for RouteError {
+  fn description(&self) -> &str {
+    match *self {
+      RouteError::Invalid { ref reason, .. } => reason,
+      RouteError::Conflict { ref reason, .. } => reason,
+    }
+  }
+}
@@ -0,0 +1,690 @@
+use std::{collections::{BTreeMap, HashMap}, fs::{self, File}, io::{self, Read}};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use autoindex;
+use config::{CacheConfig, DotfilePolicy, LogLevel, SiteConfig, SymlinkPolicy};
+use logger::Context;
+use utils;
+
+/// A file which is served together with
+/// the mime type it is served as.
+#[derive(Debug)]
+pub struct StaticFile {
+  pub contents: FileContents,
+  pub content_type: String,
+}
+
+#[derive(Debug)]
+pub enum FileContents {
+  /// The bytes of the file, which are kept in the cache.
+  Memory(Arc<Vec<u8>>),
+  /// The open file with its length, which is sent from disk.
+  Disk(File, u64),
+}
+
+/// Where the contents of a file came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheLookup {
+  Hit,
+  Miss,
+  /// The file is too large for the cache.
+  Bypass,
+}
+
+impl CacheLookup {
+  /// The name used as the label of the metric.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      CacheLookup::Hit => "hit",
+      CacheLookup::Miss => "miss",
+      CacheLookup::Bypass => "bypass",
+    }
+  }
+}
+
+/// Keeps the contents of the files which were used last in memory,
+/// up to a total size. A file is read again when its modification
+/// time or size on disk differs from when it was cached.
+pub struct FileCache {
+  config: CacheConfig,
+  state: Mutex<CacheState>,
+}
+
+struct CacheState {
+  entries: HashMap<PathBuf, CacheEntry>,
+  /// The paths of the entries by when they were last used,
+  /// so that the first one is evicted first.
+  order: BTreeMap<u64, PathBuf>,
+  size: usize,
+  uses: u64,
+}
+
+struct CacheEntry {
+  contents: Arc<Vec<u8>>,
+  modified: Option<SystemTime>,
+  len: u64,
+  last_used: u64,
+}
+
+impl FileCache {
+  pub fn new(config: CacheConfig) -> FileCache {
+    FileCache {
+      config,
+      state: Mutex::new(CacheState {
+        entries: HashMap::new(),
+        order: BTreeMap::new(),
+        size: 0,
+        uses: 0,
+      }),
+    }
+  }
+
+  /// Gives the contents of the file, which is open at the path, from
+  /// the cache if the file has not changed since it was cached. Files
+  /// which are too large for the cache are sent from disk instead. The
+  /// metadata is the one of the open file, so that the contents are
+  /// always the ones of the file it describes.
+  pub fn read(
+    &self,
+    path: &Path,
+    mut file: File,
+    metadata: &fs::Metadata,
+  ) -> io::Result<(FileContents, CacheLookup)> {
+    let modified = metadata.modified().ok();
+    let len = metadata.len();
+    if len > self.config.max_file_size as u64 {
+      return Ok((FileContents::Disk(file, len), CacheLookup::Bypass));
+    }
+
+    if let Some(contents) = self.lock().get(path, modified, len) {
+      return Ok((FileContents::Memory(contents), CacheLookup::Hit));
+    }
+    let mut bytes = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut bytes)?;
+    let contents = Arc::new(bytes);
+    let entry = CacheEntry {
+      contents: Arc::clone(&contents),
+      modified,
+      len,
+      last_used: 0,
+    };
+    self
+      .lock()
+      .insert(path.to_path_buf(), entry, self.config.max_size);
+    Ok((FileContents::Memory(contents), CacheLookup::Miss))
+  }
+
+  /// The number of files in the cache and their total size.
+  pub fn usage(&self) -> (usize, usize) {
+    let state = self.lock();
+    (state.entries.len(), state.size)
+  }
+
+  fn lock<'a>(&'a self) -> MutexGuard<'a, CacheState> {
+    match self.state.lock() {
+      Ok(state) => state,
+      Err(poisoned) => poisoned.into_inner(),
+    }
+  }
+}
+
+impl CacheState {
+  fn next_use(&mut self) -> u64 {
+    self.uses += 1;
+    self.uses
+  }
+
+  /// The contents of the entry, unless there is none or the
+  /// file has changed, in which case the entry is removed.
+  fn get(&mut self, path: &Path, modified: Option<SystemTime>, len: u64) -> Option<Arc<Vec<u8>>> {
+    let now = self.next_use();
+    match self.entries.get_mut(path) {
+      Some(ref mut entry) if entry.modified == modified && entry.len == len => {
+        self.order.remove(&entry.last_used);
+        entry.last_used = now;
+        self.order.insert(now, path.to_path_buf());
+        return Some(Arc::clone(&entry.contents));
+      }
+      Some(_) => {}
+      None => return None,
+    }
+    self.remove(path);
+    None
+  }
+
+  /// Adds the entry, evicting the entries which were used the
+  /// longest time ago until it fits.
+  fn insert(&mut self, path: PathBuf, mut entry: CacheEntry, max_size: usize) {
+    self.remove(&path);
+    let size = entry.contents.len();
+    if size > max_size {
+      return;
+    }
+    while self.size + size > max_size {
+      let oldest = match self.order.values().next() {
+        Some(oldest) => oldest.clone(),
+        None => break,
+      };
+      self.remove(&oldest);
+    }
+
+    entry.last_used = self.next_use();
+    self.order.insert(entry.last_used, path.clone());
+    self.size += size;
+    self.entries.insert(path, entry);
+  }
+
+  fn remove(&mut self, path: &Path) {
+    if let Some(entry) = self.entries.remove(path) {
+      self.order.remove(&entry.last_used);
+      self.size -= entry.contents.len();
+    }
+  }
+}
+
+/// Finds the files of a site on disk for the paths of requests.
+pub struct StaticFiles {
+  root: PathBuf,
+  /// The root with every link resolved, which the
+  /// files have to be inside of.
+  canonical_root: PathBuf,
+  index: Vec<String>,
+  mime: HashMap<String, String>,
+  symlinks: SymlinkPolicy,
+  dotfiles: DotfilePolicy,
+  autoindex: Vec<String>,
+  error_pages: HashMap<u16, PathBuf>,
+  cache: Arc<FileCache>,
+}
+
+impl StaticFiles {
+  pub fn new(site: &SiteConfig, cache: Arc<FileCache>) -> StaticFiles {
+    StaticFiles {
+      root: site.root.clone(),
+      canonical_root: fs::canonicalize(&site.root).unwrap_or_else(|_| site.root.clone()),
+      index: site.index.clone(),
+      mime: site.mime.clone(),
+      symlinks: site.symlinks,
+      dotfiles: site.dotfiles,
+      autoindex: site.autoindex.clone(),
+      error_pages: site.error_pages.clone(),
+      cache,
+    }
+  }
+
+  /// Reads the file at a path given by `resolve` through the cache.
+  /// The path is checked again after the file is opened, and the
+  /// file is only read if it is the one which passed the check, so
+  /// that a link which replaces it in between is not followed.
+  pub fn read(&self, file_path: &Path) -> io::Result<(StaticFile, CacheLookup)> {
+    let file = File::open(file_path)?;
+    let metadata = file.metadata()?;
+    let is_checked = self
+      .metadata(file_path)
+      .map_or(false, |checked| is_same_file(&checked, &metadata));
+    if !is_checked {
+      return Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "file changed after it was resolved",
+      ));
+    }
+    let (contents, lookup) = self.cache.read(file_path, file, &metadata)?;
+    let file = StaticFile {
+      contents,
+      content_type: utils::mime_type(file_path, &self.mime),
+    };
+    Ok((file, lookup))
+  }
+
+  /// The text of the document which is sent for an error status with
+  /// its mime type, from the configuration or named after the status
+  /// in the root. Documents which are not text are not used.
+  pub fn error_page(&self, status_code: u16, context: &Context) -> Option<(String, String)> {
+    let path = match self.error_pages.get(&status_code) {
+      Some(path) => self.root.join(path),
+      None => self.root.join(format!("{}.html", status_code)),
+    };
+    if !self.is_file(&path) {
+      return None;
+    }
+    match self.read(&path) {
+      Ok((
+        StaticFile {
+          contents: FileContents::Memory(bytes),
+          content_type,
+        },
+        _,
+      )) => String::from_utf8(bytes.to_vec())
+        .ok()
+        .map(|text| (text, content_type)),
+      Ok(_) => None,
+      Err(e) => {
+        log!(
+          LogLevel::Error,
+          "file",
+          context,
+          "unable to read {}: {}",
+          path.display(),
+          e
+        );
+        None
+      }
+    }
+  }
+
+  /// The path on disk of the file served at the path of a request.
+  /// Every segment of the path is percent-decoded, and paths which
+  /// could lead outside of the root are never resolved, such as ones
+  /// with `..` or an encoded `/`. A path ending in `/` is served by
+  /// the first of the index files which is in the directory.
+  pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+    let (last, dir_path) = self.split_path(path)?;
+    if last.is_empty() {
+      return self
+        .index
+        .iter()
+        .map(|name| dir_path.join(name))
+        .find(|candidate| self.is_file(candidate));
+    }
+    let file_path = dir_path.join(self.decode_segment(last)?);
+    if self.is_file(&file_path) {
+      Some(file_path)
+    } else {
+      None
+    }
+  }
+
+  /// The directory on disk which is listed at the path of a request.
+  /// Only paths ending in `/` which are in one of the directories of
+  /// `autoindex` are listed, and only when `resolve` finds no file.
+  pub fn resolve_listing(&self, path: &str) -> Option<PathBuf> {
+    if !self
+      .autoindex
+      .iter()
+      .any(|dir| path.starts_with(dir.as_str()))
+    {
+      return None;
+    }
+    match self.split_path(path)? {
+      ("", ref dir_path) if self.metadata(dir_path).map_or(false, |m| m.is_dir()) => {
+        Some(dir_path.clone())
+      }
+      _ => None,
+    }
+  }
+
+  /// The path with or without the trailing `/`, whichever serves the
+  /// file or directory the path names, when the path itself serves
+  /// nothing, such as `/about/` for `/about`.
+  pub fn canonical_path(&self, path: &str) -> Option<String> {
+    if path.ends_with('/') {
+      let without_slash = &path[..path.len() - 1];
+      match self.resolve(without_slash) {
+        Some(_) => Some(without_slash.to_string()),
+        None => None,
+      }
+    } else {
+      let with_slash = format!("{}/", path);
+      if self.resolve(&with_slash).is_some() || self.resolve_listing(&with_slash).is_some() {
+        Some(with_slash)
+      } else {
+        None
+      }
+    }
+  }
+
+  /// The files and directories in a directory given by `resolve_listing`,
+  /// leaving out the ones which could not be requested.
+  pub fn list(&self, dir_path: &Path) -> io::Result<Vec<autoindex::Entry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir_path)? {
+      let entry = entry?;
+      let name = match entry.file_name().into_string() {
+        Ok(name) => name,
+        Err(_) => continue,
+      };
+      if self.decode_segment(&utils::percent_encode(&name)).is_none() {
+        continue;
+      }
+      if let Some(metadata) = self.metadata(&entry.path()) {
+        entries.push(autoindex::Entry {
+          name,
+          is_dir: metadata.is_dir(),
+          size: metadata.len(),
+          modified: metadata.modified().ok(),
+        });
+      }
+    }
+    Ok(entries)
+  }
+
+  /// Splits the path of a request into its last segment, which is
+  /// empty for a directory, and the directory on disk it is in.
+  fn split_path<'a>(&self, path: &'a str) -> Option<(&'a str, PathBuf)> {
+    if !path.starts_with('/') {
+      return None;
+    }
+    let segments: Vec<&str> = path[1..].split('/').collect();
+    let (last, dirs) = segments.split_last()?;
+
+    let mut dir_path = self.root.clone();
+    for dir in dirs {
+      dir_path.push(self.decode_segment(dir)?);
+    }
+    Some((last, dir_path))
+  }
+
+  /// Decodes a segment of a path, unless it is empty, leads out of
+  /// its directory, names more than one segment or is a dotfile
+  /// which is not served.
+  fn decode_segment(&self, segment: &str) -> Option<String> {
+    let decoded = String::from_utf8(utils::percent_decode(segment)?).ok()?;
+    let is_safe = !decoded.is_empty()
+      && decoded != "."
+      && decoded != ".."
+      && !decoded.contains(|c| c == '/' || c == '\\' || c == '\0');
+    let is_hidden = decoded.starts_with('.') && self.dotfiles == DotfilePolicy::Deny;
+    if is_safe && !is_hidden {
+      Some(decoded)
+    } else {
+      None
+    }
+  }
+
+  fn is_file(&self, file_path: &Path) -> bool {
+    self
+      .metadata(file_path)
+      .map_or(false, |metadata| metadata.is_file())
+  }
+
+  /// The metadata of what is at a path inside the root, following
+  /// links as far as the symlink policy of the site allows.
+  fn metadata(&self, path: &Path) -> Option<fs::Metadata> {
+    match self.symlinks {
+      SymlinkPolicy::Follow => fs::metadata(path).ok(),
+      SymlinkPolicy::InsideRoot => {
+        let real_path = fs::canonicalize(path).ok()?;
+        if real_path.starts_with(&self.canonical_root) {
+          fs::metadata(real_path).ok()
+        } else {
+          None
+        }
+      }
+      SymlinkPolicy::Never => {
+        let mut current = self.root.clone();
+        let mut metadata = fs::metadata(&current).ok()?;
+        for component in path.strip_prefix(&self.root).ok()?.components() {
+          current.push(component);
+          metadata = fs::symlink_metadata(&current).ok()?;
+          if metadata.file_type().is_symlink() {
+            return None;
+          }
+        }
+        Some(metadata)
+      }
+    }
+  }
+}
+
+/// Whether two metadata are of the same file on disk.
+#[cfg(unix)]
+fn is_same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+  use std::os::unix::fs::MetadataExt;
+
+  a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn is_same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+  a.len() == b.len() && a.modified().ok() == b.modified().ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::process;
+
+  #[test]
+  fn least_recently_used_files_are_evicted() {
+    let dir = env::temp_dir().join(format!("web-server-cache-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Unable to create dir");
+    for &(name, size) in &[("a", 40), ("b", 40), ("c", 40), ("big", 200)] {
+      fs::write(dir.join(name), "x".repeat(size)).expect("Unable to write file");
+    }
+    let cache = FileCache::new(CacheConfig {
+      max_size: 100,
+      max_file_size: 50,
+    });
+    let read = |name: &str| {
+      let path = dir.join(name);
+      let file = File::open(&path).expect("Unable to open file");
+      let metadata = file.metadata().expect("Unable to read metadata");
+      cache
+        .read(&path, file, &metadata)
+        .expect("Unable to read file")
+    };
+    let lookup = |name: &str| read(name).1;
+
+    assert_eq!(CacheLookup::Miss, lookup("a"));
+    assert_eq!(CacheLookup::Miss, lookup("b"));
+    assert_eq!(CacheLookup::Hit, lookup("a"));
+    // `b` was used the longest time ago, so it makes room for `c`
+    assert_eq!(CacheLookup::Miss, lookup("c"));
+    assert_eq!((2, 80), cache.usage());
+    assert_eq!(CacheLookup::Hit, lookup("a"));
+    assert_eq!(CacheLookup::Miss, lookup("b"));
+    assert_eq!(CacheLookup::Bypass, lookup("big"));
+
+    fs::write(dir.join("a"), "changed").expect("Unable to write file");
+    match read("a") {
+      (FileContents::Memory(ref contents), CacheLookup::Miss) => {
+        assert_eq!(b"changed", &contents[..])
+      }
+      other => panic!("Expected changed file to be read again, got: {:?}", other),
+    }
+
+    // Files which are not text are cached too
+    fs::write(dir.join("binary"), b"\xff\xfe\0").expect("Unable to write file");
+    assert_eq!(CacheLookup::Miss, lookup("binary"));
+    assert_eq!(CacheLookup::Hit, lookup("binary"));
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn resolve_paths_inside_the_root() {
+    let mut site = SiteConfig::new("./html/");
+    site.index = vec!["missing.html".to_string(), "index.html".to_string()];
+    let files = StaticFiles::new(&site, Arc::new(FileCache::new(CacheConfig::default())));
+
+    assert_eq!(Some(PathBuf::from("./html/index.html")), files.resolve("/"));
+    assert_eq!(
+      Some(PathBuf::from("./html/about/index.html")),
+      files.resolve("/about/")
+    );
+    assert_eq!(
+      Some(PathBuf::from("./html/about/index.html")),
+      files.resolve("/about/index.html")
+    );
+    assert_eq!(
+      Some(PathBuf::from("./html/about/us/index.html")),
+      files.resolve("/about/us/")
+    );
+    assert_eq!(
+      Some(PathBuf::from("./html/about/index.html")),
+      files.resolve("/%61bout/index%2ehtml")
+    );
+    for path in &[
+      "/about",
+      "/missing.html",
+      "about/index.html",
+      "/about/index.html/",
+    ] {
+      assert_eq!(
+        None,
+        files.resolve(path),
+        "Path should not resolve: {}",
+        path
+      );
+    }
+
+    assert_eq!(Some("/about/".to_string()), files.canonical_path("/about"));
+    assert_eq!(
+      Some("/about/us/".to_string()),
+      files.canonical_path("/about/us")
+    );
+    assert_eq!(
+      Some("/about/index.html".to_string()),
+      files.canonical_path("/about/index.html/")
+    );
+    for path in &[
+      "/missing",
+      "/missing/",
+      "//about",
+      "//index.html/",
+      "/about/../about",
+    ] {
+      assert_eq!(
+        None,
+        files.canonical_path(path),
+        "Path should not be redirected: {}",
+        path
+      );
+    }
+  }
+
+  #[test]
+  fn traversal_payloads_are_rejected() {
+    let files = StaticFiles::new(
+      &SiteConfig::new("./html/about/"),
+      Arc::new(FileCache::new(CacheConfig::default())),
+    );
+    assert_eq!(
+      Some(PathBuf::from("./html/about/index.html")),
+      files.resolve("/index.html")
+    );
+
+    let payloads = [
+      "/../index.html",
+      "/about/../../index.html",
+      "/./index.html",
+      "//index.html",
+      "/..%2findex.html",
+      "/..%2Findex.html",
+      "/%2e%2e/index.html",
+      "/%2E%2E/index.html",
+      "/%2e%2e%2findex.html",
+      "/us/%2e%2e/%2e%2e/index.html",
+      "/..%5cindex.html",
+      "/..\\index.html",
+      "/us%2findex.html",
+      "/us%2Findex.html",
+      "/index.html%00",
+      "/index.html%00.png",
+      "/%00/index.html",
+      "/%252e%252e/index.html",
+      "/%c0%ae%c0%ae/index.html",
+      "/%e0%80%ae%e0%80%ae/index.html",
+      "/%2",
+      "/%zz/index.html",
+      "/%+1/index.html",
+      "/.%2e/index.html",
+      "/%2e./index.html",
+    ];
+    for path in &payloads {
+      assert_eq!(
+        None,
+        files.resolve(path),
+        "Path should not resolve: {}",
+        path
+      );
+    }
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn dotfile_and_symlink_policies() {
+    use std::os::unix::fs::symlink;
+
+    let dir = env::temp_dir().join(format!("web-server-links-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let root = dir.join("root");
+    fs::create_dir_all(&root).expect("Unable to create dir");
+    for name in &["root/page.html", "root/a b.html", "root/.env", "secret.txt"] {
+      fs::write(dir.join(name), "x").expect("Unable to write file");
+    }
+    symlink(root.join("page.html"), root.join("inside.html")).expect("Unable to link");
+    symlink(dir.join("secret.txt"), root.join("outside.txt")).expect("Unable to link");
+    symlink(&dir, root.join("parent")).expect("Unable to link");
+
+    let resolve = |symlinks, dotfiles, path: &str| {
+      let mut site = SiteConfig::new(&root);
+      site.symlinks = symlinks;
+      site.dotfiles = dotfiles;
+      StaticFiles::new(&site, Arc::new(FileCache::new(CacheConfig::default())))
+        .resolve(path)
+        .is_some()
+    };
+    let inside_root = |path: &str| resolve(SymlinkPolicy::InsideRoot, DotfilePolicy::Deny, path);
+    assert!(inside_root("/a%20b.html"));
+    assert!(inside_root("/inside.html"));
+    assert!(!inside_root("/outside.txt"));
+    assert!(!inside_root("/parent/secret.txt"));
+    assert!(!inside_root("/.env"));
+    assert!(!inside_root("/%2eenv"));
+
+    assert!(resolve(
+      SymlinkPolicy::Follow,
+      DotfilePolicy::Deny,
+      "/outside.txt"
+    ));
+    assert!(resolve(
+      SymlinkPolicy::Follow,
+      DotfilePolicy::Deny,
+      "/parent/secret.txt"
+    ));
+    assert!(resolve(
+      SymlinkPolicy::Never,
+      DotfilePolicy::Deny,
+      "/page.html"
+    ));
+    assert!(!resolve(
+      SymlinkPolicy::Never,
+      DotfilePolicy::Deny,
+      "/inside.html"
+    ));
+    assert!(!resolve(
+      SymlinkPolicy::Never,
+      DotfilePolicy::Deny,
+      "/parent/root/page.html"
+    ));
+    assert!(resolve(
+      SymlinkPolicy::InsideRoot,
+      DotfilePolicy::Allow,
+      "/.env"
+    ));
+
+    // A file which is swapped for a link after it was resolved is not read
+    for &symlinks in &[SymlinkPolicy::InsideRoot, SymlinkPolicy::Never] {
+      let mut site = SiteConfig::new(&root);
+      site.symlinks = symlinks;
+      let files = StaticFiles::new(&site, Arc::new(FileCache::new(CacheConfig::default())));
+      fs::write(root.join("swapped.html"), "x").expect("Unable to write file");
+      let path = files
+        .resolve("/swapped.html")
+        .expect("Unable to resolve file");
+      fs::remove_file(&path).expect("Unable to remove file");
+      symlink(dir.join("secret.txt"), &path).expect("Unable to link");
+      assert!(
+        files.read(&path).is_err(),
+        "Link was followed with {:?}",
+        symlinks
+      );
+      fs::remove_file(&path).expect("Unable to remove link");
+    }
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
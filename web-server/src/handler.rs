@@ -0,0 +1,19 @@
+use http;
+
+/// Something which answers requests. Handlers are shared between
+/// the worker threads, so they have to be `Send` and `Sync`.
+///
+/// Closures taking a request and returning a response are
+/// handlers, so small endpoints do not need their own type.
+pub trait Handler: Send + Sync {
+  fn handle(&self, request: &http::Request) -> http::Response;
+}
+
+impl<F> Handler for F
+where
+  F: Fn(&http::Request) -> http::Response + Send + Sync,
+{
+  fn handle(&self, request: &http::Request) -> http::Response {
+    self(request)
+  }
+}
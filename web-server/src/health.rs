@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Set by `SIGTERM` and `SIGINT`, which ask the server to stop.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the server is ready for requests. It is not ready
+/// while sites are being loaded or once it is stopping.
+pub struct Health {
+  started: Instant,
+  loading: AtomicUsize,
+  stopping: AtomicBool,
+}
+
+impl Health {
+  pub fn new() -> Health {
+    Health {
+      started: Instant::now(),
+      loading: AtomicUsize::new(0),
+      stopping: AtomicBool::new(false),
+    }
+  }
+
+  /// Marks the server as not ready until the guard is dropped.
+  pub fn loading<'a>(&'a self) -> LoadingGuard<'a> {
+    self.loading.fetch_add(1, Ordering::SeqCst);
+    LoadingGuard { health: self }
+  }
+
+  /// Marks the server as stopping, which it stays until it exits.
+  pub fn stop(&self) {
+    self.stopping.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_stopping(&self) -> bool {
+    self.stopping.load(Ordering::SeqCst)
+  }
+
+  /// Why the server is not ready, or `None` if it is.
+  pub fn not_ready_reason(&self) -> Option<&'static str> {
+    if self.is_stopping() {
+      Some("stopping")
+    } else if self.loading.load(Ordering::SeqCst) > 0 {
+      Some("loading")
+    } else {
+      None
+    }
+  }
+
+  pub fn uptime(&self) -> Duration {
+    self.started.elapsed()
+  }
+}
+
+/// Marks the server as ready again when dropped, unless
+/// other sites are still being loaded.
+pub struct LoadingGuard<'a> {
+  health: &'a Health,
+}
+
+impl<'a> Drop for LoadingGuard<'a> {
+  fn drop(&mut self) {
+    self.health.loading.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// Whether `SIGTERM` or `SIGINT` has been received.
+pub fn stop_requested() -> bool {
+  STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Makes `SIGTERM` and `SIGINT` ask the server to stop,
+/// instead of ending the process right away.
+#[cfg(unix)]
+pub fn stop_on_signals() {
+  extern "C" fn on_signal(_: ::libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+  }
+
+  let handler = on_signal as extern "C" fn(::libc::c_int);
+  unsafe {
+    ::libc::signal(::libc::SIGTERM, handler as ::libc::sighandler_t);
+    ::libc::signal(::libc::SIGINT, handler as ::libc::sighandler_t);
+  }
+}
+
+#[cfg(not(unix))]
+pub fn stop_on_signals() {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn not_ready_while_loading_or_stopping() {
+    let health = Health::new();
+    assert_eq!(None, health.not_ready_reason());
+    {
+      let _loading = health.loading();
+      let _other = health.loading();
+      assert_eq!(Some("loading"), health.not_ready_reason());
+    }
+    assert_eq!(None, health.not_ready_reason());
+
+    let _loading = health.loading();
+    health.stop();
+    assert_eq!(Some("stopping"), health.not_ready_reason());
+  }
+}
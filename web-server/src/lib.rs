@@ -1,42 +1,678 @@
 #![feature(try_from, try_trait, iterator_try_fold)]
 
 extern crate http;
+#[cfg(unix)]
+extern crate libc;
 
+#[macro_use]
+mod logger;
+pub mod cli;
+pub mod config;
+mod access_log;
+mod admin;
+mod autoindex;
 mod router;
 mod utils;
 mod errors;
+mod files;
+mod handler;
+mod health;
+mod metrics;
+mod middleware;
+mod pool;
+mod reader;
+mod reload;
+mod route;
+mod toml;
+mod transfer;
+mod vhost;
 
-use std::{convert::TryFrom, io::Write, net::{TcpListener, TcpStream}, path::Path};
-use router::Router;
-use errors::HandleStreamError;
-use http::ParseHttpError;
+use std::{convert::TryFrom, io::Write, thread};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use access_log::AccessLog;
+use admin::{Admin, Status};
+use files::FileCache;
+use health::Health;
+use reload::LiveReload;
+use logger::Context;
+use metrics::Metrics;
+use middleware::Pipeline;
+use router::{Router, Routes};
+use errors::{HandleStreamError, ReadStreamError};
+use pool::ThreadPool;
+use reader::{RequestBody, RequestReader};
+use config::{AdminConfig, Limits, LogLevel, Timeouts};
+use vhost::VirtualHosts;
+use http::{ParseHttpError, content::Contentable};
 
-pub fn run(port: usize) {
-  let dir_path = Path::new("./html/");
-  let router = Router::new(dir_path);
+pub use cli::Options;
+pub use config::Config;
+pub use errors::{ConfigError, ParseArgsError, RouteError};
+pub use handler::Handler;
+pub use middleware::Middleware;
 
-  let listener = TcpListener::bind(format!("localhost:{}", port))
-    .expect("Unable to start listening for TCP-packets.");
+/// Serves the sites in the configuration with only their
+/// static files. See `Server` for adding dynamic routes.
+pub fn run(config: Config) {
+  Server::new(config).run()
+}
+
+/// A server for the sites in a configuration, with middleware and
+/// handlers for dynamic routes added next to the static files.
+///
+/// ```no_run
+/// # extern crate http;
+/// # extern crate web_server;
+/// # use web_server::{Config, RouteError, Server};
+/// # fn main() { run().expect("Unable to add route") }
+/// # fn run() -> Result<(), RouteError> {
+/// Server::new(Config::default())
+///   .route(http::RequestMethod::GET, "/api/users/:id", |req: &http::Request| {
+///     let id = req.param("id").unwrap_or_default();
+///     http::Response::new(http::StatusCode::OK, format!("User {}", id))
+///   })?
+///   .run();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server {
+  config: Config,
+  routes: Routes,
+  pipeline: Pipeline,
+}
+
+impl Server {
+  pub fn new(config: Config) -> Server {
+    Server {
+      config,
+      routes: Routes::new(),
+      pipeline: Pipeline::new(),
+    }
+  }
+
+  /// Adds a handler for requests with the method to the paths
+  /// matching the pattern on every site of the server. Patterns
+  /// can have parameters such as `:id` and a wildcard such as
+  /// `*rest`, which are available from `Request::param`. Gives an
+  /// error if the pattern is invalid or conflicts with a route
+  /// which has already been added.
+  pub fn route<H>(
+    mut self,
+    method: http::RequestMethod,
+    pattern: &str,
+    handler: H,
+  ) -> Result<Server, RouteError>
+  where
+    H: Handler + 'static,
+  {
+    router::add_route(&mut self.routes, method, pattern, Arc::new(handler))?;
+    Ok(self)
+  }
+
+  /// Adds middleware which runs for every request, after the
+  /// middleware which has already been added.
+  pub fn middleware<M: Middleware + 'static>(self, middleware: M) -> Server {
+    self.middleware_for("/", middleware)
+  }
+
+  /// Adds middleware which runs for the requests to paths
+  /// under the prefix, such as `/api`.
+  pub fn middleware_for<M: Middleware + 'static>(mut self, prefix: &str, middleware: M) -> Server {
+    self.pipeline.add(prefix, Arc::new(middleware));
+    self
+  }
+
+  /// Starts listening on every address in the configuration and
+  /// serves the sites until the process is asked to stop with
+  /// `SIGTERM` or `SIGINT`. Open connections are then given
+  /// time to finish before the server exits.
+  pub fn run(self) {
+    let config = self.config;
+    let mut routes = self.routes;
+    let mut pipeline = self.pipeline;
+    let metrics = Arc::new(Metrics::new());
+    let health = Arc::new(Health::new());
+    let cache = Arc::new(FileCache::new(config.cache));
+    let admin = Admin {
+      metrics: Arc::clone(&metrics),
+      health: Arc::clone(&health),
+      cache: Arc::clone(&cache),
+      status: Arc::new(RwLock::new(Status::default())),
+    };
+
+    let mut admin_routes = Routes::new();
+    admin::add_health_routes(&mut routes, Arc::clone(&health));
+    if let Some(ref admin_config) = config.admin {
+      admin::add_health_routes(&mut admin_routes, Arc::clone(&health));
+      admin::add_routes(&mut admin_routes, admin_config, &admin);
+      // Without a listener of their own, the admin pages are served on every
+      // site, which the configuration only allows when every site protects them
+      if admin_config.listen.is_none() {
+        admin::add_routes(&mut routes, admin_config, &admin);
+      }
+    }
+
+    // Pages are told to reload when the files change in dev mode
+    let live_reload = Arc::new(LiveReload::new());
+    if config.dev {
+      let events: Arc<Handler> = live_reload.clone();
+      if let Err(e) = router::add_route(
+        &mut routes,
+        http::RequestMethod::GET,
+        reload::EVENTS_PATH,
+        events,
+      ) {
+        panic!("Unable to add live reload: {}", e);
+      }
+      pipeline.add("/", live_reload.clone());
+    }
+
+    // The listeners answer with the loading routes until the sites
+    // are loaded, so that `/readyz` can tell that they are loading
+    let loading = health.loading();
+    logger::set_level(config.logging.level);
+    logger::change_level_on_signals();
+    let access_log = AccessLog::new(&config.logging).expect("Unable to open access log");
+    access_log::reopen_on_sighup();
+    health::stop_on_signals();
+    let shared = Arc::new(Shared {
+      limits: config.limits,
+      timeouts: config.timeouts,
+      access_log,
+      metrics: Arc::clone(&metrics),
+      health: Arc::clone(&health),
+    });
+    let pool = Arc::new(ThreadPool::new(config.threads));
+
+    let mut addresses = Vec::new();
+    if let Some(AdminConfig {
+      listen: Some(ref address),
+      ..
+    }) = config.admin
+    {
+      let router = Router::from_routes(admin_routes).with_metrics(Arc::clone(&metrics));
+      let (_, address) = listen(address, VirtualHosts::new(Arc::new(router)), &shared, &pool);
+      addresses.push(address);
+    }
+    let loading_routes = admin::loading_routes(Arc::clone(&health));
+    let loading_router = Arc::new(Router::from_routes(loading_routes));
+    let listeners: Vec<_> = config
+      .listeners()
+      .into_iter()
+      .map(|(address, sites)| {
+        let hosts = VirtualHosts::new(Arc::clone(&loading_router));
+        let (hosts, address) = listen(&address, hosts, &shared, &pool);
+        addresses.push(address);
+        (hosts, sites)
+      })
+      .collect();
+
+    let routers: Vec<Arc<Router>> = config
+      .sites
+      .iter()
+      .map(|site| {
+        let router = Router::with_routes(site, routes.clone(), Arc::clone(&cache))
+          .with_pipeline(pipeline.clone())
+          .with_metrics(Arc::clone(&metrics));
+        Arc::new(router)
+      })
+      .collect();
+    if let Ok(mut status) = admin.status.write() {
+      *status = Status::new(&config, &routes);
+    }
+    for (listener_hosts, sites) in listeners {
+      let default = sites
+        .iter()
+        .cloned()
+        .find(|&i| config.sites[i].hosts.is_empty())
+        .unwrap_or(sites[0]);
+      let mut hosts = VirtualHosts::new(Arc::clone(&routers[default]));
+      for &i in &sites {
+        for host in &config.sites[i].hosts {
+          hosts.add_host(host, Arc::clone(&routers[i]));
+        }
+      }
+      match listener_hosts.write() {
+        Ok(mut current) => *current = Arc::new(hosts),
+        Err(poisoned) => *poisoned.into_inner() = Arc::new(hosts),
+      }
+    }
+    drop(loading);
+
+    if config.dev {
+      watch_sites(&config, Arc::clone(&live_reload));
+    }
+
+    while !health::stop_requested() {
+      thread::sleep(Duration::from_millis(100));
+    }
+    health.stop();
+    live_reload.close();
+    for &address in &addresses {
+      wake(address);
+    }
+    log!(
+      LogLevel::Info,
+      "shutdown",
+      &Context::default(),
+      "stopping, waiting for {} open connections",
+      metrics.active_connections()
+    );
+    let deadline = Instant::now() + config.timeouts.shutdown;
+    while metrics.active_connections() > 0 && Instant::now() < deadline {
+      thread::sleep(Duration::from_millis(100));
+    }
+    log!(
+      LogLevel::Info,
+      "shutdown",
+      &Context::default(),
+      "stopped with {} open connections",
+      metrics.active_connections()
+    );
+  }
+}
+
+/// Tells the pages in the browser to reload when the files
+/// of a site change. The files themselves are read again
+/// when they are requested, since the cache sees the change.
+fn watch_sites(config: &Config, live_reload: Arc<LiveReload>) {
+  let roots = config.sites.iter().map(|site| site.root.clone()).collect();
+  let sites = config.sites.clone();
+
+  let keep_alive = Arc::clone(&live_reload);
+  thread::spawn(move || loop {
+    thread::sleep(Duration::from_secs(reload::KEEP_ALIVE_SECS));
+    keep_alive.keep_alive();
+  });
+
+  thread::spawn(move || {
+    reload::watch(roots, |i, changes| {
+      log!(
+        LogLevel::Info,
+        "reload",
+        &Context::default(),
+        "{} changed files in {}",
+        changes,
+        sites[i].root.display()
+      );
+      live_reload.notify();
+    })
+  });
+}
+
+/// The sites served on a listener, which can be changed while
+/// it is accepting connections.
+type ListenerHosts = Arc<RwLock<Arc<VirtualHosts>>>;
+
+/// Starts accepting connections to the address on a thread of its
+/// own, until the server stops. Gives the sites of the listener, so
+/// that they can be changed, and the address it listens on.
+fn listen(
+  address: &str,
+  hosts: VirtualHosts,
+  shared: &Arc<Shared>,
+  pool: &Arc<ThreadPool>,
+) -> (ListenerHosts, SocketAddr) {
+  let listener = TcpListener::bind(address).expect("Unable to start listening for TCP-packets.");
+  let local_address = listener
+    .local_addr()
+    .expect("Unable to get the address of the listener.");
+  log!(
+    LogLevel::Info,
+    "listen",
+    &Context::default(),
+    "listening for connections at {}",
+    address
+  );
+
+  let hosts = Arc::new(RwLock::new(Arc::new(hosts)));
+  let listener_hosts = Arc::clone(&hosts);
+  let shared = Arc::clone(shared);
+  let pool = Arc::clone(pool);
+  thread::spawn(move || accept_connections(listener, listener_hosts, shared, pool));
+  (hosts, local_address)
+}
 
-  println!("Listening for connections at port {}", port);
+/// Connects to a listener, which is waiting for a connection, so
+/// that it sees that the server is stopping and stops accepting.
+fn wake(mut address: SocketAddr) {
+  if address.ip().is_unspecified() {
+    let loopback = match address {
+      SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+      SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)),
+    };
+    address.set_ip(loopback);
+  }
+  let _ = TcpStream::connect_timeout(&address, Duration::from_secs(1));
+}
+
+/// What the connections of every listener share.
+struct Shared {
+  limits: Limits,
+  timeouts: Timeouts,
+  access_log: AccessLog,
+  metrics: Arc<Metrics>,
+  health: Arc<Health>,
+}
+
+/// Counts connections to give each one a number for the logs.
+static CONNECTION_COUNT: AtomicUsize = AtomicUsize::new(0);
 
+fn accept_connections(
+  listener: TcpListener,
+  hosts: ListenerHosts,
+  shared: Arc<Shared>,
+  pool: Arc<ThreadPool>,
+) {
   for stream in listener.incoming() {
-    match stream
+    // Dropping the listener closes it, so that new
+    // connections are refused while stopping
+    if shared.health.is_stopping() {
+      break;
+    }
+    match stream {
+      Ok(s) => {
+        let mut context = Context {
+          connection: Some(CONNECTION_COUNT.fetch_add(1, Ordering::SeqCst)),
+          peer: s.peer_addr().map(|addr| addr.to_string()).ok(),
+          request_id: None,
+        };
+        log!(LogLevel::Debug, "accept", &context, "accepted connection");
+
+        let hosts = match hosts.read() {
+          Ok(hosts) => Arc::clone(&hosts),
+          Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        };
+        let shared = Arc::clone(&shared);
+        pool.execute(
+          move || match handle_stream(&hosts, &shared, &mut context, s) {
+            Ok(requests) => log!(
+              LogLevel::Debug,
+              "close",
+              &context,
+              "closed after {} requests",
+              requests
+            ),
+            Err(HandleStreamError::ReadStream(ref e @ ReadStreamError::Timeout(_)))
+            | Err(HandleStreamError::ReadStream(ref e @ ReadStreamError::TooSlow)) => {
+              log!(LogLevel::Info, "close", &context, "closed: {}", e)
+            }
+            Err(HandleStreamError::Io(ref e)) => {
+              log!(LogLevel::Warn, "write", &context, "closed: {}", e)
+            }
+            Err(ref e) if e.status_code().is_some() => {
+              log!(LogLevel::Info, "parse", &context, "closed: {}", e)
+            }
+            Err(e) => log!(LogLevel::Warn, "close", &context, "closed: {}", e),
+          },
+        );
+      }
+      Err(e) => log!(LogLevel::Error, "accept", &Context::default(), "{}", e),
+    };
+  }
+}
+
+/// Serves requests on a connection until the client closes it,
+/// asks for it to be closed or does not send a request in time.
+/// Gives the number of requests which were answered.
+fn handle_stream(
+  h: &VirtualHosts,
+  shared: &Shared,
+  context: &mut Context,
+  s: TcpStream,
+) -> Result<usize, HandleStreamError> {
+  let _connection = shared.metrics.connection();
+  s.set_write_timeout(Some(shared.timeouts.write))?;
+  let client = s
+    .peer_addr()
+    .map(|addr| addr.ip().to_string())
+    .unwrap_or_else(|_| "-".to_string());
+  // The body of a request is read from the reader by the handler,
+  // so the reader is shared with the request
+  let reader = Arc::new(Mutex::new(RequestReader::new(
+    s.try_clone()?,
+    shared.limits,
+    shared.timeouts,
+  )));
+  let mut writer = &s;
+  let mut requests = 0;
+
+  loop {
+    context.request_id = None;
+    let request = reader::lock(&reader)
+      .read_head()
       .map_err(HandleStreamError::from)
-      .and_then(|s| handle_stream(&router, s))
-    {
-      Ok(_) => println!("Sent response to user"),
-      Err(e) => eprintln!("Error: {}", e),
+      .and_then(|head| {
+        shared.metrics.record_received(head.len());
+        http::Request::try_from(head)
+          .map_err(ParseHttpError::from)
+          .map_err(HandleStreamError::from)
+      });
+    let started = reader::lock(&reader).request_started();
+
+    let mut entry = access_log::Entry {
+      client: client.clone(),
+      time: SystemTime::now(),
+      request_line: None,
+      status: 0,
+      bytes: 0,
+      referer: None,
+      user_agent: None,
+      duration: started.elapsed(),
+    };
+
+    let mut req = match request {
+      Ok(req) => req,
+      Err(HandleStreamError::ReadStream(ReadStreamError::Closed)) => return Ok(requests),
+      Err(e) => {
+        let pages = |res: &mut http::Response| h.add_error_body(None, None, res, context);
+        return Err(reject(&s, shared, &mut entry, started, &pages, e));
+      }
     };
+
+    let request_id = req
+      .has_header("X-Request-Id")
+      .filter(|id| logger::is_valid_request_id(id))
+      .map(|id| id.to_string())
+      .unwrap_or_else(logger::next_request_id);
+    context.request_id = Some(request_id.clone());
+    log!(
+      LogLevel::Trace,
+      "parse",
+      context,
+      "parsed {} {}",
+      req.method(),
+      req.uri()
+    );
+
+    entry.request_line = Some(format!("{} {} {}", req.method(), req.uri(), req.version()));
+    entry.referer = req.has_header("Referer").map(|s| s.to_string());
+    entry.user_agent = req.has_header("User-Agent").map(|s| s.to_string());
+
+    // Connections are closed after every request while stopping
+    let mut keep_alive = keep_alive(&req) && !shared.health.is_stopping();
+    let version = req.version();
+    let method = req.method();
+    let is_head = method == http::RequestMethod::HEAD;
+
+    // A client which expects `100 Continue` waits for it before it sends
+    // the body, so it is only sent once the handler starts to read
+    let expects_continue = req
+      .has_header("Expect")
+      .map_or(false, |expect| expect.eq_ignore_ascii_case("100-continue"));
+    if expects_continue && version == http::HttpVersion::Http11 {
+      reader::lock(&reader).expect_continue();
+    }
+    req.set_body_reader(RequestBody::new(Arc::clone(&reader)));
+    let host = req.has_header("Host").map(|host| host.to_string());
+    let accept = req.has_header("Accept").map(|accept| accept.to_string());
+    let mut res = h.handle_request(req, context);
+
+    // The rest of the body is read so that the next request can be, and an
+    // error while reading it is answered instead of the response
+    let finished = reader::lock(&reader).finish_body();
+    shared
+      .metrics
+      .record_received(reader::lock(&reader).body_read());
+    match finished {
+      Ok(true) => {}
+      Ok(false) => keep_alive = false,
+      Err(e) => {
+        let pages = |res: &mut http::Response| {
+          h.add_error_body(
+            host.as_ref().map(|s| s.as_str()),
+            accept.as_ref().map(|s| s.as_str()),
+            res,
+            context,
+          )
+        };
+        return Err(reject(
+          &s,
+          shared,
+          &mut entry,
+          started,
+          &pages,
+          HandleStreamError::from(e),
+        ));
+      }
+    }
+    log!(
+      LogLevel::Debug,
+      "route",
+      context,
+      "{} answered with {}",
+      entry.request_line.as_ref().map_or("-", |s| s.as_str()),
+      res.status_code() as u16
+    );
+
+    res.add_header("X-Request-Id", request_id.as_str());
+    // Framing headers set by the handler could conflict with the ones set here
+    res.remove_header("Content-Length");
+    res.remove_header("Transfer-Encoding");
+    // A body whose length is not known is chunked for HTTP/1.1 clients,
+    // and ends when the connection is closed for HTTP/1.0 clients
+    let mut chunked = false;
+    if res.status_code().allows_body() {
+      match res.content_length() {
+        Some(length) => {
+          res.add_header("Content-Length", length.to_string().as_str());
+        }
+        None if version == http::HttpVersion::Http11 => {
+          res.add_header("Transfer-Encoding", "chunked");
+          chunked = true;
+        }
+        None => keep_alive = false,
+      }
+    }
+    // The response to `HEAD` has the headers of the response to `GET`, but no body,
+    // and responses with a status which allows no body are sent without one
+    let mut body = res.take_body();
+    if is_head || !res.status_code().allows_body() {
+      res.set_body("");
+      body = None;
+    }
+    // A body from a channel stays open for as long as its sender wants,
+    // such as for events, so it is sent on a thread of its own rather
+    // than keeping the worker from other connections. The connection
+    // is closed after it.
+    let streams = match body {
+      Some(http::Body::Channel(_)) => true,
+      _ => false,
+    };
+    if streams {
+      keep_alive = false;
+    }
+    if !keep_alive {
+      res.add_header("Connection", "close");
+    }
+    let res_str = res.to_string();
+    writer.write_all(res_str.as_bytes())?;
+    let mut body_bytes = res.get_body().len();
+    match body {
+      Some(body @ http::Body::Channel(_)) => {
+        let stream = s.try_clone()?;
+        // Stopping waits for the body, as for a connection
+        let active = Metrics::stream(&shared.metrics);
+        thread::spawn(move || {
+          let _active = active;
+          transfer::send_body(&stream, body, chunked)
+        });
+      }
+      Some(body) => body_bytes = transfer::send_body(&s, body, chunked)? as usize,
+      None => {}
+    }
+    let sent = res_str.len() - res.get_body().len() + body_bytes;
+    log!(LogLevel::Trace, "write", context, "wrote {} bytes", sent);
+
+    entry.status = res.status_code() as u16;
+    entry.bytes = body_bytes;
+    entry.duration = started.elapsed();
+    shared.access_log.log(&entry);
+    shared.metrics.record_sent(sent);
+    shared
+      .metrics
+      .record_request(method, res.status_code(), entry.duration);
+    requests += 1;
+
+    if !keep_alive {
+      return Ok(requests);
+    }
   }
 }
 
-fn handle_stream(r: &Router, mut s: TcpStream) -> Result<(), HandleStreamError> {
-  let request_str = utils::read_string_from_stream(&mut s)?;
+/// Whether the connection should stay open after the request.
+/// HTTP/1.1 connections are persistent unless closed by the
+/// client, HTTP/1.0 connections only when asked for.
+fn keep_alive(req: &http::Request) -> bool {
+  let connection = req
+    .has_header("Connection")
+    .map(|value| value.to_lowercase())
+    .unwrap_or_default();
+  let has_token = |token: &str| connection.split(',').any(|t| t.trim() == token);
+
+  match req.version() {
+    http::HttpVersion::Http11 => !has_token("close"),
+    http::HttpVersion::Http10 => has_token("keep-alive"),
+  }
+}
+
+/// Answers a request which could not be read with the status of
+/// the error, if it has one, and logs it. Gives back the error.
+fn reject(
+  s: &TcpStream,
+  shared: &Shared,
+  entry: &mut access_log::Entry,
+  started: Instant,
+  pages: &Fn(&mut http::Response),
+  e: HandleStreamError,
+) -> HandleStreamError {
+  if let Some(res) = send_error(s, pages, &e) {
+    shared.metrics.record_parse_error(e.kind_name());
+    shared.metrics.record_sent(res.to_string().len());
+    entry.status = res.status_code() as u16;
+    entry.bytes = res.get_body().len();
+    entry.duration = started.elapsed();
+    shared.access_log.log(entry);
+  }
+  e
+}
 
-  http::Request::try_from(request_str)
-    .map_err(ParseHttpError::from)
-    .map_err(HandleStreamError::from)
-    .map(|req| r.handle_request(req))
-    .and_then(|res| write!(s, "{}", res).map_err(HandleStreamError::from))
+/// Tells the client why its request could not be handled, if the
+/// error is one which has a status, with the error page of the site.
+/// The connection is closed after.
+fn send_error(
+  mut s: &TcpStream,
+  pages: &Fn(&mut http::Response),
+  e: &HandleStreamError,
+) -> Option<http::Response> {
+  e.status_code().map(|status_code| {
+    let mut res = http::Response::new(status_code, "");
+    pages(&mut res);
+    res.add_header("Connection", "close");
+    let _ = write!(s, "{}", res);
+    res
+  })
 }
@@ -0,0 +1,167 @@
+use std::{fmt, io::{self, Write}, process};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use config::LogLevel;
+use utils::DateTime;
+
+/// Writes a log line to stderr if the level is enabled. The line
+/// has the time, the level, the event, the fields of the context
+/// and the message, such as:
+///
+/// `2000-10-10T13:55:36Z INFO event=accept conn=1 peer=127.0.0.1:4000 accepted connection`
+macro_rules! log {
+  ($level:expr, $event:expr, $context:expr, $($arg:tt)+) => {
+    if $crate::logger::enabled($level) {
+      $crate::logger::write($level, $event, $context, format_args!($($arg)+));
+    }
+  };
+}
+
+/// The index of the current level in `LogLevel::ALL`.
+static LEVEL: AtomicUsize = AtomicUsize::new(2);
+
+/// Counts requests to give each one an id.
+static REQUEST_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn index_of(level: LogLevel) -> usize {
+  LogLevel::ALL.iter().position(|&l| l == level).unwrap_or(0)
+}
+
+/// Sets the least severe level which is logged.
+/// Can be changed while the server is running.
+pub fn set_level(level: LogLevel) {
+  LEVEL.store(index_of(level), Ordering::SeqCst);
+}
+
+pub fn enabled(level: LogLevel) -> bool {
+  index_of(level) <= LEVEL.load(Ordering::SeqCst)
+}
+
+/// Makes `SIGUSR1` log more and `SIGUSR2` log less, so
+/// that the level can be changed without a restart.
+#[cfg(unix)]
+pub fn change_level_on_signals() {
+  extern "C" fn on_signal(signal: ::libc::c_int) {
+    let current = LEVEL.load(Ordering::SeqCst);
+    let next = if signal == ::libc::SIGUSR1 {
+      (current + 1).min(LogLevel::ALL.len() - 1)
+    } else {
+      current.saturating_sub(1)
+    };
+    LEVEL.store(next, Ordering::SeqCst);
+  }
+
+  let handler = on_signal as extern "C" fn(::libc::c_int);
+  unsafe {
+    ::libc::signal(::libc::SIGUSR1, handler as ::libc::sighandler_t);
+    ::libc::signal(::libc::SIGUSR2, handler as ::libc::sighandler_t);
+  }
+}
+
+#[cfg(not(unix))]
+pub fn change_level_on_signals() {}
+
+/// What a log line is about. Fields which are not known
+/// are left out of the line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context {
+  pub connection: Option<usize>,
+  pub peer: Option<String>,
+  pub request_id: Option<String>,
+}
+
+impl fmt::Display for Context {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(connection) = self.connection {
+      write!(f, " conn={}", connection)?;
+    }
+    if let Some(ref peer) = self.peer {
+      write!(f, " peer={}", peer)?;
+    }
+    if let Some(ref request_id) = self.request_id {
+      write!(f, " req={}", request_id)?;
+    }
+    Ok(())
+  }
+}
+
+pub fn write(level: LogLevel, event: &str, context: &Context, message: fmt::Arguments) {
+  let time = DateTime::from_system_time(SystemTime::now());
+  let line = format!(
+    "{} {:5} event={}{} {}",
+    time.to_rfc3339(),
+    level.to_string().to_uppercase(),
+    event,
+    context,
+    message
+  );
+  let _ = writeln!(io::stderr(), "{}", line);
+}
+
+/// Gives the next request an id which is unique for the process.
+pub fn next_request_id() -> String {
+  let count = REQUEST_COUNT.fetch_add(1, Ordering::SeqCst);
+  format!("{:08x}-{:06x}", run_id(), count)
+}
+
+/// A number which differs between runs of the server, so that
+/// request ids from different runs do not collide in the logs.
+fn run_id() -> u32 {
+  static RUN_ID: AtomicUsize = AtomicUsize::new(0);
+  let id = RUN_ID.load(Ordering::SeqCst);
+  if id != 0 {
+    return id as u32;
+  }
+
+  let secs = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  let candidate = (secs as u32 ^ process::id()) | 1;
+  match RUN_ID.compare_exchange(0, candidate as usize, Ordering::SeqCst, Ordering::SeqCst) {
+    Ok(_) => candidate,
+    Err(existing) => existing as u32,
+  }
+}
+
+/// Whether a request id sent by the client can be used, so that
+/// ids from a proxy in front of the server carry through.
+pub fn is_valid_request_id(id: &str) -> bool {
+  !id.is_empty()
+    && id.len() <= 64
+    && id
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn context_fields() {
+    assert_eq!("", Context::default().to_string());
+    let context = Context {
+      connection: Some(3),
+      peer: Some("127.0.0.1:4000".to_string()),
+      request_id: Some("abc-1".to_string()),
+    };
+    assert_eq!(" conn=3 peer=127.0.0.1:4000 req=abc-1", context.to_string());
+  }
+
+  #[test]
+  fn request_ids_are_unique() {
+    let first = next_request_id();
+    let second = next_request_id();
+    assert_ne!(first, second);
+    assert_eq!(
+      first[..8],
+      second[..8],
+      "Ids from the same run should share the first part"
+    );
+    assert!(is_valid_request_id(&first));
+    assert!(!is_valid_request_id("id with spaces"));
+    assert!(!is_valid_request_id(""));
+  }
+}
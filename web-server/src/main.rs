@@ -1,5 +1,37 @@
 extern crate web_server;
 
+use std::{env, process};
+use web_server::{Config, cli::{self, Action}};
+
 fn main() {
-  web_server::run(8080)
+  let options = match cli::parse_args(env::args().skip(1)) {
+    Ok(Action::Run(options)) => options,
+    Ok(Action::Help) => return print!("{}", cli::usage()),
+    Ok(Action::Version) => return println!("web-server {}", cli::VERSION),
+    Err(e) => {
+      eprintln!("web-server: {}", e);
+      eprintln!("Try 'web-server --help' for more information.");
+      process::exit(2);
+    }
+  };
+
+  let config =
+    Config::from_options(&options).and_then(|config| config.check_environment().map(|_| config));
+  let config = match config {
+    Ok(config) => config,
+    Err(e) => {
+      match options.config {
+        Some(ref path) => eprintln!("web-server: {}: {}", path.display(), e),
+        None => eprintln!("web-server: {}", e),
+      }
+      process::exit(1);
+    }
+  };
+
+  if options.check_config {
+    println!("web-server: configuration is valid");
+    return;
+  }
+
+  web_server::run(config)
 }
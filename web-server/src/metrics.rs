@@ -0,0 +1,350 @@
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use http;
+
+/// The upper bounds in seconds of the buckets of the
+/// request duration histogram.
+const DURATION_BUCKETS: [f64; 11] = [
+  0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counters for how the server is performing, which are
+/// written in the Prometheus text exposition format.
+pub struct Metrics {
+  requests: Mutex<BTreeMap<(String, u16), usize>>,
+  duration_buckets: Vec<AtomicUsize>,
+  duration_count: AtomicUsize,
+  duration_sum_micros: AtomicUsize,
+  received_bytes: AtomicUsize,
+  sent_bytes: AtomicUsize,
+  active_connections: AtomicUsize,
+  connections: AtomicUsize,
+  parse_errors: Mutex<BTreeMap<&'static str, usize>>,
+  route_hits: AtomicUsize,
+  route_misses: AtomicUsize,
+  file_cache: Mutex<BTreeMap<&'static str, usize>>,
+}
+
+impl Metrics {
+  pub fn new() -> Metrics {
+    Metrics {
+      requests: Mutex::new(BTreeMap::new()),
+      duration_buckets: DURATION_BUCKETS
+        .iter()
+        .map(|_| AtomicUsize::new(0))
+        .collect(),
+      duration_count: AtomicUsize::new(0),
+      duration_sum_micros: AtomicUsize::new(0),
+      received_bytes: AtomicUsize::new(0),
+      sent_bytes: AtomicUsize::new(0),
+      active_connections: AtomicUsize::new(0),
+      connections: AtomicUsize::new(0),
+      parse_errors: Mutex::new(BTreeMap::new()),
+      route_hits: AtomicUsize::new(0),
+      route_misses: AtomicUsize::new(0),
+      file_cache: Mutex::new(BTreeMap::new()),
+    }
+  }
+
+  /// Counts a request which has been answered.
+  pub fn record_request(
+    &self,
+    method: http::RequestMethod,
+    status: http::StatusCode,
+    duration: Duration,
+  ) {
+    if let Ok(mut requests) = self.requests.lock() {
+      *requests
+        .entry((method.to_string(), status as u16))
+        .or_insert(0) += 1;
+    }
+
+    let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+    if let Some(bucket) = DURATION_BUCKETS.iter().position(|&bound| seconds <= bound) {
+      self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+    self.duration_count.fetch_add(1, Ordering::Relaxed);
+    self
+      .duration_sum_micros
+      .fetch_add((seconds * 1e6) as usize, Ordering::Relaxed);
+  }
+
+  pub fn record_received(&self, bytes: usize) {
+    self.received_bytes.fetch_add(bytes, Ordering::Relaxed);
+  }
+
+  pub fn record_sent(&self, bytes: usize) {
+    self.sent_bytes.fetch_add(bytes, Ordering::Relaxed);
+  }
+
+  /// Counts a connection as active until the guard is dropped.
+  pub fn connection<'a>(&'a self) -> ConnectionGuard<'a> {
+    self.connections.fetch_add(1, Ordering::Relaxed);
+    self.active_connections.fetch_add(1, Ordering::Relaxed);
+    ConnectionGuard { metrics: self }
+  }
+
+  /// Counts a body which is sent on a thread of its own, after the
+  /// rest of its connection was handled, as an active connection
+  /// until the guard is dropped.
+  pub fn stream(metrics: &Arc<Metrics>) -> StreamGuard {
+    metrics.active_connections.fetch_add(1, Ordering::Relaxed);
+    StreamGuard {
+      metrics: Arc::clone(metrics),
+    }
+  }
+
+  pub fn active_connections(&self) -> usize {
+    self.active_connections.load(Ordering::Relaxed)
+  }
+
+  pub fn total_connections(&self) -> usize {
+    self.connections.load(Ordering::Relaxed)
+  }
+
+  /// Counts a request which could not be read or parsed.
+  pub fn record_parse_error(&self, kind: &'static str) {
+    if let Ok(mut parse_errors) = self.parse_errors.lock() {
+      *parse_errors.entry(kind).or_insert(0) += 1;
+    }
+  }
+
+  /// Counts whether a path was found in the route table.
+  pub fn record_route_lookup(&self, hit: bool) {
+    if hit {
+      self.route_hits.fetch_add(1, Ordering::Relaxed);
+    } else {
+      self.route_misses.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  /// Counts a static file which was served, by whether it
+  /// was in the cache.
+  pub fn record_file_cache(&self, lookup: &'static str) {
+    if let Ok(mut file_cache) = self.file_cache.lock() {
+      *file_cache.entry(lookup).or_insert(0) += 1;
+    }
+  }
+
+  /// Writes every metric in the Prometheus text exposition format.
+  pub fn render(&self) -> String {
+    let mut out = String::new();
+
+    header(
+      &mut out,
+      "web_server_requests_total",
+      "counter",
+      "Requests answered by method and status.",
+    );
+    if let Ok(requests) = self.requests.lock() {
+      for (&(ref method, status), count) in requests.iter() {
+        let _ = writeln!(
+          out,
+          "web_server_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+          method, status, count
+        );
+      }
+    }
+
+    header(
+      &mut out,
+      "web_server_request_duration_seconds",
+      "histogram",
+      "Time from the first byte of a request until the response was written.",
+    );
+    let mut cumulative = 0;
+    for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+      cumulative += bucket.load(Ordering::Relaxed);
+      let _ = writeln!(
+        out,
+        "web_server_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+        bound, cumulative
+      );
+    }
+    let count = self.duration_count.load(Ordering::Relaxed);
+    let _ = writeln!(
+      out,
+      "web_server_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+      count
+    );
+    let sum = self.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+    let _ = writeln!(out, "web_server_request_duration_seconds_sum {}", sum);
+    let _ = writeln!(out, "web_server_request_duration_seconds_count {}", count);
+
+    let counters = [
+      (
+        "web_server_received_bytes_total",
+        "counter",
+        "Bytes of requests received.",
+        &self.received_bytes,
+      ),
+      (
+        "web_server_sent_bytes_total",
+        "counter",
+        "Bytes of responses sent.",
+        &self.sent_bytes,
+      ),
+      (
+        "web_server_connections_total",
+        "counter",
+        "Connections accepted.",
+        &self.connections,
+      ),
+      (
+        "web_server_active_connections",
+        "gauge",
+        "Connections which are open.",
+        &self.active_connections,
+      ),
+    ];
+    for &(name, kind, help, value) in &counters {
+      header(&mut out, name, kind, help);
+      let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+    }
+
+    header(
+      &mut out,
+      "web_server_parse_errors_total",
+      "counter",
+      "Requests which could not be read by kind of error.",
+    );
+    if let Ok(parse_errors) = self.parse_errors.lock() {
+      for (kind, count) in parse_errors.iter() {
+        let _ = writeln!(
+          out,
+          "web_server_parse_errors_total{{kind=\"{}\"}} {}",
+          kind, count
+        );
+      }
+    }
+
+    header(
+      &mut out,
+      "web_server_route_lookups_total",
+      "counter",
+      "Lookups in the route table by result.",
+    );
+    let _ = writeln!(
+      out,
+      "web_server_route_lookups_total{{result=\"hit\"}} {}",
+      self.route_hits.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+      out,
+      "web_server_route_lookups_total{{result=\"miss\"}} {}",
+      self.route_misses.load(Ordering::Relaxed)
+    );
+
+    header(
+      &mut out,
+      "web_server_file_cache_lookups_total",
+      "counter",
+      "Static files served by cache result.",
+    );
+    if let Ok(file_cache) = self.file_cache.lock() {
+      for (result, count) in file_cache.iter() {
+        let _ = writeln!(
+          out,
+          "web_server_file_cache_lookups_total{{result=\"{}\"}} {}",
+          result, count
+        );
+      }
+    }
+
+    out
+  }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+  let _ = writeln!(out, "# HELP {} {}", name, help);
+  let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Marks a connection as closed when dropped.
+pub struct ConnectionGuard<'a> {
+  metrics: &'a Metrics,
+}
+
+impl<'a> Drop for ConnectionGuard<'a> {
+  fn drop(&mut self) {
+    self
+      .metrics
+      .active_connections
+      .fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn render_recorded_metrics() {
+    let metrics = Metrics::new();
+    metrics.record_request(
+      http::RequestMethod::GET,
+      http::StatusCode::OK,
+      Duration::from_millis(20),
+    );
+    metrics.record_request(
+      http::RequestMethod::GET,
+      http::StatusCode::OK,
+      Duration::from_secs(20),
+    );
+    metrics.record_request(
+      http::RequestMethod::POST,
+      http::StatusCode::NotFound,
+      Duration::from_millis(1),
+    );
+    metrics.record_parse_error("unknown_method");
+    metrics.record_route_lookup(true);
+    metrics.record_file_cache("hit");
+    metrics.record_sent(100);
+    {
+      let _connection = metrics.connection();
+      assert_eq!(1, metrics.active_connections());
+    }
+
+    let rendered = metrics.render();
+    let expected_lines = vec![
+      "web_server_requests_total{method=\"GET\",status=\"200\"} 2",
+      "web_server_requests_total{method=\"POST\",status=\"404\"} 1",
+      "web_server_request_duration_seconds_bucket{le=\"0.005\"} 1",
+      "web_server_request_duration_seconds_bucket{le=\"0.025\"} 2",
+      "web_server_request_duration_seconds_bucket{le=\"10\"} 2",
+      "web_server_request_duration_seconds_bucket{le=\"+Inf\"} 3",
+      "web_server_request_duration_seconds_count 3",
+      "web_server_sent_bytes_total 100",
+      "web_server_connections_total 1",
+      "web_server_active_connections 0",
+      "web_server_parse_errors_total{kind=\"unknown_method\"} 1",
+      "web_server_route_lookups_total{result=\"hit\"} 1",
+      "web_server_file_cache_lookups_total{result=\"hit\"} 1",
+      "# TYPE web_server_request_duration_seconds histogram",
+    ];
+    for line in expected_lines {
+      assert!(
+        rendered.lines().any(|l| l == line),
+        "Missing line: {}\n{}",
+        line,
+        rendered
+      );
+    }
+  }
+}
+
+/// Marks a streamed body as finished when dropped.
+pub struct StreamGuard {
+  metrics: Arc<Metrics>,
+}
+
+impl Drop for StreamGuard {
+  fn drop(&mut self) {
+    self
+      .metrics
+      .active_connections
+      .fetch_sub(1, Ordering::Relaxed);
+  }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use http;
+use utils;
+
+/// Behavior around the handling of requests, such as logging,
+/// authentication or extra headers. Middleware is run in the order
+/// it is added on the way in, and in the reverse order on the way
+/// out, so the first middleware sees the final response.
+pub trait Middleware: Send + Sync {
+  /// Called before the request is routed. Returning a response
+  /// answers the request without running the rest of the pipeline,
+  /// but the middleware which has already run still sees it.
+  fn before(&self, _request: &mut http::Request) -> Option<http::Response> {
+    None
+  }
+
+  /// Called with the response before it is sent.
+  fn after(&self, _request: &http::Request, _response: &mut http::Response) {}
+}
+
+/// The middleware of a site, each for the paths under a prefix.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+  layers: Vec<(String, Arc<Middleware>)>,
+}
+
+impl Pipeline {
+  pub fn new() -> Pipeline {
+    Pipeline { layers: Vec::new() }
+  }
+
+  /// Adds middleware for the paths under the prefix. The
+  /// prefix `/` gives middleware which runs for every request.
+  pub fn add<S: Into<String>>(&mut self, prefix: S, middleware: Arc<Middleware>) {
+    self
+      .layers
+      .push((utils::normalize_path(&prefix.into()), middleware));
+  }
+
+  /// Runs the request through the middleware for its path,
+  /// with `next` giving the response at the end of the pipeline.
+  pub fn handle<F>(&self, mut request: http::Request, next: F) -> http::Response
+  where
+    F: FnOnce(&mut http::Request) -> http::Response,
+  {
+    let layers: Vec<&Arc<Middleware>> = self
+      .layers
+      .iter()
+      .filter(|&&(ref prefix, _)| has_prefix(request.path(), prefix))
+      .map(|&(_, ref middleware)| middleware)
+      .collect();
+
+    let mut ran = 0;
+    let mut early_response = None;
+    for middleware in &layers {
+      ran += 1;
+      early_response = middleware.before(&mut request);
+      if early_response.is_some() {
+        break;
+      }
+    }
+
+    let mut response = match early_response {
+      Some(response) => response,
+      None => next(&mut request),
+    };
+    for middleware in layers[..ran].iter().rev() {
+      middleware.after(&request, &mut response);
+    }
+    response
+  }
+}
+
+/// Whether the path is under the prefix. `/api` is a prefix
+/// of `/api` and `/api/users`, but not of `/apis`.
+pub fn has_prefix(path: &str, prefix: &str) -> bool {
+  if !path.starts_with(prefix) {
+    return false;
+  }
+  prefix.ends_with('/') || path.len() == prefix.len() || path[prefix.len()..].starts_with('/')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Mutex;
+  use http::content::Contentable;
+
+  /// Records the order it is run in, and stops requests
+  /// when it has been told to.
+  struct Record {
+    name: &'static str,
+    stop: bool,
+    log: Arc<Mutex<Vec<String>>>,
+  }
+
+  impl Middleware for Record {
+    fn before(&self, _request: &mut http::Request) -> Option<http::Response> {
+      self
+        .log
+        .lock()
+        .unwrap()
+        .push(format!("before {}", self.name));
+      if self.stop {
+        Some(http::Response::new(http::StatusCode::Forbidden, ""))
+      } else {
+        None
+      }
+    }
+
+    fn after(&self, _request: &http::Request, response: &mut http::Response) {
+      self
+        .log
+        .lock()
+        .unwrap()
+        .push(format!("after {}", self.name));
+      response.add_header("X-Seen-By", self.name);
+    }
+  }
+
+  fn pipeline(layers: &[(&str, &'static str, bool)], log: &Arc<Mutex<Vec<String>>>) -> Pipeline {
+    let mut pipeline = Pipeline::new();
+    for &(prefix, name, stop) in layers {
+      let log = Arc::clone(log);
+      pipeline.add(prefix, Arc::new(Record { name, stop, log }));
+    }
+    pipeline
+  }
+
+  fn ok(_: &mut http::Request) -> http::Response {
+    http::Response::new(http::StatusCode::OK, "")
+  }
+
+  #[test]
+  fn middleware_runs_in_order() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let pipeline = pipeline(
+      &[
+        ("/", "a", false),
+        ("/api", "b", false),
+        ("/other", "c", false),
+      ],
+      &log,
+    );
+
+    let res = pipeline.handle(
+      http::Request::new(http::RequestMethod::GET, "/api/users"),
+      ok,
+    );
+    assert_eq!(http::StatusCode::OK, res.status_code());
+    assert_eq!(Some("a"), res.has_header("X-Seen-By"));
+    assert_eq!(
+      vec!["before a", "before b", "after b", "after a"],
+      *log.lock().unwrap()
+    );
+  }
+
+  #[test]
+  fn middleware_can_stop_request() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let pipeline = pipeline(
+      &[("/", "a", false), ("/", "b", true), ("/", "c", false)],
+      &log,
+    );
+
+    let res = pipeline.handle(http::Request::new(http::RequestMethod::GET, "/"), ok);
+    assert_eq!(http::StatusCode::Forbidden, res.status_code());
+    assert_eq!(
+      vec!["before a", "before b", "after b", "after a"],
+      *log.lock().unwrap()
+    );
+  }
+
+  #[test]
+  fn prefix_matches_whole_segments() {
+    assert!(has_prefix("/api", "/api"));
+    assert!(has_prefix("/api/users", "/api"));
+    assert!(has_prefix("/api/users", "/api/"));
+    assert!(has_prefix("/anything", "/"));
+    assert!(!has_prefix("/apis", "/api"));
+  }
+}
@@ -0,0 +1,85 @@
+use std::{panic::{self, AssertUnwindSafe}, sync::{mpsc, Arc, Mutex}, thread};
+
+type Job = Box<FnOnce() + Send + 'static>;
+
+/// A fixed amount of threads which executes jobs sent
+/// to the pool. Used so that a slow connection does not
+/// stop the server from answering other connections.
+pub struct ThreadPool {
+  workers: Vec<thread::JoinHandle<()>>,
+  sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+  pub fn new(size: usize) -> ThreadPool {
+    assert!(size > 0, "A thread pool needs at least one thread");
+
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    let workers = (0..size)
+      .map(|id| {
+        let receiver = Arc::clone(&receiver);
+        thread::Builder::new()
+          .name(format!("worker-{}", id))
+          .spawn(move || loop {
+            let job = match receiver.lock() {
+              Ok(receiver) => receiver.recv(),
+              Err(_) => break,
+            };
+            match job {
+              // A job which panics must not take its worker with it,
+              // or the pool runs out of workers. The panic has been
+              // printed by the panic hook already.
+              Ok(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+              }
+              Err(_) => break,
+            }
+          })
+          .expect("Unable to spawn worker thread")
+      })
+      .collect();
+
+    ThreadPool {
+      workers,
+      sender: Some(sender),
+    }
+  }
+
+  /// Queues a job which will be run by the first free worker.
+  pub fn execute<F>(&self, f: F)
+  where
+    F: FnOnce() + Send + 'static,
+  {
+    if let Some(ref sender) = self.sender {
+      let _ = sender.send(Box::new(f));
+    }
+  }
+}
+
+impl Drop for ThreadPool {
+  /// Lets the workers finish the queued jobs before
+  /// the pool is dropped.
+  fn drop(&mut self) {
+    drop(self.sender.take());
+    for worker in self.workers.drain(..) {
+      let _ = worker.join();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  #[test]
+  fn workers_survive_panicking_jobs() {
+    let pool = ThreadPool::new(1);
+    let (sender, receiver) = mpsc::channel();
+    pool.execute(|| panic!("the job failed"));
+    pool.execute(move || sender.send("done").expect("Unable to send"));
+    assert_eq!(Ok("done"), receiver.recv_timeout(Duration::from_secs(5)));
+  }
+}
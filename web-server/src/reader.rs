@@ -0,0 +1,793 @@
+use std::{io::{self, Read, Write}, net::TcpStream, time::{Duration, Instant}};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use config::{Limits, Timeouts};
+use errors::{ReadStreamError, TimeoutKind};
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Room for the method and version around the uri in the request line.
+const REQUEST_LINE_OVERHEAD: usize = 32;
+
+/// The transfer rate is not checked until a request has been
+/// received for this long, so short stalls are allowed.
+const MIN_RATE_GRACE: Duration = Duration::from_secs(2);
+
+/// A stream which can limit how long a read waits.
+pub trait TimeoutRead: Read {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl TimeoutRead for TcpStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_read_timeout(self, timeout)
+  }
+}
+
+impl<'a> TimeoutRead for &'a TcpStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_read_timeout(self, timeout)
+  }
+}
+
+/// Reads requests from a stream. The head is read until the empty
+/// line which ends it, and the body is read as it is needed, as
+/// framed by `Content-Length` or `Transfer-Encoding: chunked`.
+/// Bytes which are read past the end of a request are kept for
+/// the next request on the same stream.
+///
+/// The head of a request has to be received before its deadline,
+/// and the body without waiting longer than the body timeout for
+/// any read. Clients which send slower than the minimum rate, over
+/// the time spent waiting for them, are dropped.
+pub struct RequestReader<R> {
+  inner: R,
+  buf: Vec<u8>,
+  limits: Limits,
+  timeouts: Timeouts,
+  phase: Phase,
+  requests_read: usize,
+  request_started: Instant,
+  body: BodyState,
+  body_read: usize,
+  body_error: Option<ReadStreamError>,
+  awaits_continue: bool,
+}
+
+/// The part of the request which is being read, and how long
+/// was spent waiting for the bytes of it which were received.
+struct Phase {
+  kind: TimeoutKind,
+  started: Instant,
+  timeout: Duration,
+  /// When the whole part has to be received by, or `None`
+  /// if every read may wait for as long as the timeout.
+  deadline: Option<Instant>,
+  waited: Duration,
+  received: usize,
+}
+
+impl Phase {
+  fn new(kind: TimeoutKind, timeout: Duration) -> Phase {
+    let mut phase = Phase::per_read(kind, timeout);
+    phase.deadline = Some(phase.started + timeout);
+    phase
+  }
+
+  fn per_read(kind: TimeoutKind, timeout: Duration) -> Phase {
+    Phase {
+      kind,
+      started: Instant::now(),
+      timeout,
+      deadline: None,
+      waited: Duration::from_secs(0),
+      received: 0,
+    }
+  }
+}
+
+/// How the length of the body of a request is given.
+#[derive(Debug, PartialEq)]
+enum Framing {
+  Length(usize),
+  Chunked,
+}
+
+/// What is left of the body of the last request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BodyState {
+  Length(usize),
+  /// The bytes left of the current chunk, and
+  /// whether it is the first chunk.
+  Chunked(usize, bool),
+  Done,
+}
+
+impl<R: TimeoutRead + Write> RequestReader<R> {
+  pub fn new(inner: R, limits: Limits, timeouts: Timeouts) -> RequestReader<R> {
+    RequestReader {
+      inner,
+      buf: Vec::new(),
+      limits,
+      timeouts,
+      phase: Phase::new(TimeoutKind::Idle, timeouts.head),
+      requests_read: 0,
+      request_started: Instant::now(),
+      body: BodyState::Done,
+      body_read: 0,
+      body_error: None,
+      awaits_continue: false,
+    }
+  }
+
+  /// Reads the head of the next request and returns it as a string.
+  /// The body is read with `read_body`, and has to be read or skipped
+  /// with `finish_body` before the next request can be read.
+  pub fn read_head(&mut self) -> Result<String, ReadStreamError> {
+    self.wait_for_request()?;
+
+    self.phase = Phase::new(TimeoutKind::Head, self.timeouts.head);
+    self.request_started = self.phase.started;
+    let head_end = self.read_head_end()?;
+    let head: Vec<u8> = self.buf.drain(..head_end).collect();
+    let head = String::from_utf8(head)?;
+
+    self.body = match framing(&head)? {
+      Some(Framing::Length(length)) => {
+        if length > self.limits.max_body_size {
+          return Err(ReadStreamError::BodyTooLarge);
+        }
+        BodyState::Length(length)
+      }
+      Some(Framing::Chunked) => BodyState::Chunked(0, true),
+      None => BodyState::Done,
+    };
+    self.body_read = 0;
+    self.body_error = None;
+    self.awaits_continue = false;
+    self.requests_read += 1;
+    Ok(head)
+  }
+
+  /// Makes the first read of the body send `100 Continue` before
+  /// it reads, for a client which waits for it to send the body.
+  pub fn expect_continue(&mut self) {
+    self.awaits_continue = self.body != BodyState::Done;
+  }
+
+  /// The number of bytes of the body of the last request
+  /// which have been read.
+  pub fn body_read(&self) -> usize {
+    self.body_read
+  }
+
+  /// Reads what is left of the body, so that the next request can
+  /// be read, and gives whether it can. It cannot when the client
+  /// still waits for `100 Continue`, as it may never send the body.
+  /// An error while the body was read before is given again.
+  pub fn finish_body(&mut self) -> Result<bool, ReadStreamError> {
+    if let Some(e) = self.body_error.take() {
+      return Err(e);
+    }
+    if self.awaits_continue {
+      return Ok(false);
+    }
+    let mut scratch = [0; READ_CHUNK_SIZE];
+    while self.read_body(&mut scratch)? > 0 {}
+    Ok(true)
+  }
+
+  /// When the first byte of the last request was received.
+  pub fn request_started(&self) -> Instant {
+    self.request_started
+  }
+
+  /// Waits until the first byte of the next request is received.
+  /// The first request of a connection is given as long as the
+  /// head timeout, later requests the idle timeout.
+  fn wait_for_request(&mut self) -> Result<(), ReadStreamError> {
+    let timeout = if self.requests_read == 0 {
+      self.timeouts.head
+    } else {
+      self.timeouts.idle
+    };
+    self.phase = Phase::new(TimeoutKind::Idle, timeout);
+
+    loop {
+      // Empty lines before the request line are ignored (RFC 7230, 3.5)
+      let leading_newlines = self
+        .buf
+        .iter()
+        .take_while(|&&b| b == b'\r' || b == b'\n')
+        .count();
+      self.buf.drain(..leading_newlines);
+
+      if !self.buf.is_empty() {
+        return Ok(());
+      }
+      if self.fill()? == 0 {
+        return Err(ReadStreamError::Closed);
+      }
+    }
+  }
+
+  /// Reads until the head is complete, and returns the
+  /// position in the buffer right after the head.
+  fn read_head_end(&mut self) -> Result<usize, ReadStreamError> {
+    loop {
+      let request_line_end = self.buf.iter().position(|&b| b == b'\n');
+      if request_line_end.unwrap_or(self.buf.len())
+        > self.limits.max_uri_length + REQUEST_LINE_OVERHEAD
+      {
+        return Err(ReadStreamError::UriTooLong);
+      }
+
+      if let Some(head_end) = find_head_end(&self.buf) {
+        if head_end > self.limits.max_header_size {
+          return Err(ReadStreamError::HeaderTooLarge);
+        }
+        let header_lines = self.buf[..head_end]
+          .split(|&b| b == b'\n')
+          .filter(|line| !line.is_empty() && *line != b"\r")
+          .count();
+        if header_lines.saturating_sub(1) > self.limits.max_headers {
+          return Err(ReadStreamError::TooManyHeaders);
+        }
+        return Ok(head_end);
+      }
+
+      if self.buf.len() > self.limits.max_header_size {
+        return Err(ReadStreamError::HeaderTooLarge);
+      }
+      if self.fill()? == 0 {
+        return Err(ReadStreamError::UnexpectedEof);
+      }
+    }
+  }
+
+  /// Reads the next part of the body into `buf`, and gives the number
+  /// of bytes which were read, which is 0 at the end of the body. A
+  /// chunked body is decoded, so only the content is given. The time
+  /// between reads, while the handler works, is not counted.
+  pub fn read_body(&mut self, buf: &mut [u8]) -> Result<usize, ReadStreamError> {
+    if buf.is_empty() || self.body == BodyState::Done {
+      return Ok(0);
+    }
+    if self.awaits_continue {
+      self.awaits_continue = false;
+      self.inner.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    if self.phase.kind != TimeoutKind::Body {
+      self.phase = Phase::per_read(TimeoutKind::Body, self.timeouts.body);
+    }
+
+    loop {
+      match self.body {
+        BodyState::Done | BodyState::Length(0) => {
+          self.body = BodyState::Done;
+          return Ok(0);
+        }
+        BodyState::Length(left) => {
+          let read = self.read_buffered(buf, left)?;
+          self.body = BodyState::Length(left - read);
+          return Ok(read);
+        }
+        BodyState::Chunked(0, first) => {
+          if !first && !self.read_line()?.is_empty() {
+            return Err(ReadStreamError::InvalidFraming);
+          }
+          let size_line = self.read_line()?;
+          let size_str = size_line.split(';').next().unwrap_or("").trim();
+          if size_str.is_empty() || !size_str.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ReadStreamError::InvalidFraming);
+          }
+          // A size too large for a `usize` is too large for any limit as well
+          let size =
+            usize::from_str_radix(size_str, 16).map_err(|_| ReadStreamError::BodyTooLarge)?;
+
+          if size == 0 {
+            // Trailers are read, but not used
+            while !self.read_line()?.is_empty() {}
+            self.body = BodyState::Done;
+            return Ok(0);
+          }
+          match self.body_read.checked_add(size) {
+            Some(total) if total <= self.limits.max_body_size => {}
+            _ => return Err(ReadStreamError::BodyTooLarge),
+          }
+          self.body = BodyState::Chunked(size, false);
+        }
+        BodyState::Chunked(left, _) => {
+          let read = self.read_buffered(buf, left)?;
+          self.body = BodyState::Chunked(left - read, false);
+          return Ok(read);
+        }
+      }
+    }
+  }
+
+  /// Reads a line which ends in `\r\n` or `\n`, without the line ending.
+  fn read_line(&mut self) -> Result<String, ReadStreamError> {
+    loop {
+      if let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+        let mut line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+          line.pop();
+        }
+        return Ok(String::from_utf8(line)?);
+      }
+      if self.buf.len() > self.limits.max_header_size {
+        return Err(ReadStreamError::InvalidFraming);
+      }
+      if self.fill()? == 0 {
+        return Err(ReadStreamError::UnexpectedEof);
+      }
+    }
+  }
+
+  /// Moves at most `max` bytes from the buffer into `buf`,
+  /// reading from the stream first if the buffer is empty.
+  fn read_buffered(&mut self, buf: &mut [u8], max: usize) -> Result<usize, ReadStreamError> {
+    if self.buf.is_empty() && self.fill()? == 0 {
+      return Err(ReadStreamError::UnexpectedEof);
+    }
+    let read = buf.len().min(max).min(self.buf.len());
+    buf[..read].copy_from_slice(&self.buf[..read]);
+    self.buf.drain(..read);
+    self.body_read += read;
+    Ok(read)
+  }
+
+  /// Reads once from the stream into the buffer, waiting at most
+  /// until the deadline of the current phase, or for as long as its
+  /// timeout if it has none.
+  fn fill(&mut self) -> Result<usize, ReadStreamError> {
+    let now = Instant::now();
+    let timeout = match self.phase.deadline {
+      Some(deadline) if now >= deadline => return Err(ReadStreamError::Timeout(self.phase.kind)),
+      Some(deadline) => deadline - now,
+      None => self.phase.timeout,
+    };
+    self.inner.set_read_timeout(Some(timeout))?;
+
+    let mut chunk = [0; READ_CHUNK_SIZE];
+    let read = self.inner.read(&mut chunk);
+    self.phase.waited += now.elapsed();
+    let bytes_read = match read {
+      Ok(bytes_read) => bytes_read,
+      // Reading with a timeout gives `WouldBlock` or `TimedOut` depending on the platform
+      Err(ref e)
+        if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+      {
+        return Err(ReadStreamError::Timeout(self.phase.kind))
+      }
+      Err(e) => return Err(ReadStreamError::from(e)),
+    };
+    self.buf.extend_from_slice(&chunk[..bytes_read]);
+
+    self.phase.received += bytes_read;
+    let waited = self.phase.waited;
+    if self.phase.kind != TimeoutKind::Idle && self.timeouts.min_rate > 0 && waited > MIN_RATE_GRACE
+    {
+      let waited_ms = waited.as_secs() * 1000 + u64::from(waited.subsec_nanos() / 1_000_000);
+      let rate = self.phase.received as u64 * 1000 / waited_ms;
+      if rate < self.timeouts.min_rate as u64 {
+        return Err(ReadStreamError::TooSlow);
+      }
+    }
+    Ok(bytes_read)
+  }
+}
+
+/// A reader which is shared between the connection
+/// and the body of the request which is handled.
+pub type SharedReader<R> = Arc<Mutex<RequestReader<R>>>;
+
+/// Locks a shared reader. A handler which panicked while reading
+/// the body ends its connection, so a poisoned lock is still used.
+pub fn lock<'a, R>(reader: &'a SharedReader<R>) -> MutexGuard<'a, RequestReader<R>> {
+  match reader.lock() {
+    Ok(reader) => reader,
+    Err(poisoned) => poisoned.into_inner(),
+  }
+}
+
+/// The body of the last request read by a reader which is
+/// shared with the connection, so that a handler can read it.
+pub struct RequestBody<R>(SharedReader<R>);
+
+impl<R> RequestBody<R> {
+  pub fn new(reader: SharedReader<R>) -> RequestBody<R> {
+    RequestBody(reader)
+  }
+}
+
+impl<R: TimeoutRead + Write> Read for RequestBody<R> {
+  /// Reading stops at the first error, which is kept to be
+  /// given again by `finish_body` once the handler is done.
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let mut reader = lock(&self.0);
+    reader.read_body(buf).map_err(|e| {
+      let kind = match e {
+        ReadStreamError::Io(ref e) => e.kind(),
+        ReadStreamError::Timeout(_) | ReadStreamError::TooSlow => io::ErrorKind::TimedOut,
+        ReadStreamError::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+        _ => io::ErrorKind::InvalidData,
+      };
+      let error = io::Error::new(kind, e.to_string());
+      reader.body = BodyState::Done;
+      reader.body_error = Some(e);
+      error
+    })
+  }
+}
+
+/// Finds the end of the head, which is ended by an empty line.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+  let crlf = buf
+    .windows(4)
+    .position(|w| w == b"\r\n\r\n")
+    .map(|pos| pos + 4);
+  let lf = buf.windows(2).position(|w| w == b"\n\n").map(|pos| pos + 2);
+  match (crlf, lf) {
+    (Some(a), Some(b)) => Some(a.min(b)),
+    (a, b) => a.or(b),
+  }
+}
+
+/// Finds how the body is framed from the headers in the head. A
+/// request with both `Content-Length` and `Transfer-Encoding` is
+/// rejected, as the two could be read differently by a proxy. No
+/// transfer coding but a single `chunked` is supported.
+fn framing(head: &str) -> Result<Option<Framing>, ReadStreamError> {
+  let mut length = None;
+  let mut codings = Vec::new();
+
+  for line in head.lines().skip(1) {
+    let mut parts = line.splitn(2, ':');
+    let (name, value) = match (parts.next(), parts.next()) {
+      (Some(name), Some(value)) => (name.trim(), value.trim()),
+      _ => continue,
+    };
+
+    if name.eq_ignore_ascii_case("Content-Length") {
+      // `parse` would take a sign as well
+      if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(ReadStreamError::InvalidFraming);
+      }
+      let new_length = value
+        .parse::<usize>()
+        .map_err(|_| ReadStreamError::BodyTooLarge)?;
+      if length.map_or(false, |l| l != new_length) {
+        return Err(ReadStreamError::InvalidFraming);
+      }
+      length = Some(new_length);
+    } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+      codings.extend(value.split(',').map(|coding| coding.trim()));
+    }
+  }
+
+  if codings.iter().any(|coding| coding.is_empty()) {
+    return Err(ReadStreamError::InvalidFraming);
+  }
+  let chunked = !codings.is_empty();
+  if codings.len() > 1
+    || codings
+      .iter()
+      .any(|coding| !coding.eq_ignore_ascii_case("chunked"))
+  {
+    return Err(ReadStreamError::UnsupportedCoding);
+  }
+
+  match (length, chunked) {
+    (Some(_), true) => Err(ReadStreamError::InvalidFraming),
+    (Some(length), false) => Ok(Some(Framing::Length(length))),
+    (None, true) => Ok(Some(Framing::Chunked)),
+    (None, false) => Ok(None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{io::Cursor, net::TcpListener, thread};
+
+  impl TimeoutRead for Cursor<Vec<u8>> {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  impl<R: TimeoutRead + Write> RequestReader<R> {
+    /// Reads the head and the whole body of the next request.
+    fn read_request(&mut self) -> Result<String, ReadStreamError> {
+      let head = self.read_head()?;
+      let mut body = Vec::new();
+      let mut buf = [0; 100];
+      loop {
+        match self.read_body(&mut buf)? {
+          0 => return Ok(head + &String::from_utf8(body)?),
+          read => body.extend_from_slice(&buf[..read]),
+        }
+      }
+    }
+  }
+
+  fn reader(input: &str) -> RequestReader<Cursor<Vec<u8>>> {
+    RequestReader::new(
+      Cursor::new(input.as_bytes().to_vec()),
+      Limits::default(),
+      Timeouts::default(),
+    )
+  }
+
+  #[test]
+  fn read_request_with_length() {
+    let body = "x".repeat(10_000);
+    let req_str = format!(
+      "PUT /new HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+      body.len(),
+      body
+    );
+    match reader(&req_str).read_request() {
+      Ok(request) => assert_eq!(req_str, request, "Request was not read whole"),
+      Err(e) => panic!("Should not get error on valid request: {}", e),
+    }
+  }
+
+  #[test]
+  fn read_pipelined_requests() {
+    let mut reader = reader("\r\nGET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+    let first = reader.read_request().expect("Unable to read first request");
+    let second = reader
+      .read_request()
+      .expect("Unable to read second request");
+
+    assert_eq!("GET /a HTTP/1.1\r\n\r\n", first);
+    assert_eq!("GET /b HTTP/1.1\r\n\r\n", second);
+    match reader.read_request() {
+      Err(ReadStreamError::Closed) => {}
+      other => panic!("Expected closed stream, got: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn read_chunked_body() {
+    let req_str = concat!(
+      "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+      "5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n"
+    );
+    match reader(req_str).read_request() {
+      Ok(request) => assert_eq!(
+        "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nhello world",
+        request
+      ),
+      Err(e) => panic!("Should not get error on valid request: {}", e),
+    }
+  }
+
+  #[test]
+  fn limits_are_enforced() {
+    let limits = Limits {
+      max_uri_length: 32,
+      max_header_size: 64,
+      max_headers: 2,
+      max_body_size: 8,
+    };
+    let read = |req_str: &str| {
+      RequestReader::new(
+        Cursor::new(req_str.as_bytes().to_vec()),
+        limits,
+        Timeouts::default(),
+      )
+      .read_request()
+    };
+
+    match read(&format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100))) {
+      Err(ReadStreamError::UriTooLong) => {}
+      other => panic!("Expected too long uri, got: {:?}", other),
+    }
+    match read(&format!(
+      "GET / HTTP/1.1\r\nCookie: {}\r\n\r\n",
+      "a".repeat(100)
+    )) {
+      Err(ReadStreamError::HeaderTooLarge) => {}
+      other => panic!("Expected too large header, got: {:?}", other),
+    }
+    match read("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n") {
+      Err(ReadStreamError::TooManyHeaders) => {}
+      other => panic!("Expected too many headers, got: {:?}", other),
+    }
+    match read("PUT / HTTP/1.1\r\nContent-Length: 9\r\n\r\n123456789") {
+      Err(ReadStreamError::BodyTooLarge) => {}
+      other => panic!("Expected too large body, got: {:?}", other),
+    }
+    match read(
+      "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n12345\r\n5\r\n12345\r\n0\r\n\r\n",
+    ) {
+      Err(ReadStreamError::BodyTooLarge) => {}
+      other => panic!("Expected too large body, got: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn invalid_framing() {
+    let invalid = vec![
+      "PUT / HTTP/1.1\r\nContent-Length: ten\r\n\r\n",
+      "PUT / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+      "PUT / HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+      "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+      "PUT / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab",
+      "PUT / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+      "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+1\r\na\r\n0\r\n\r\n",
+    ];
+    for req_str in invalid {
+      if reader(req_str).read_request().is_ok() {
+        panic!("Should get error on invalid framing: {:?}", req_str);
+      }
+    }
+
+    let too_large = vec![
+      "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n",
+      "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nfffffffffffffffffffff\r\n",
+      "PUT / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n",
+    ];
+    for req_str in too_large {
+      match reader(req_str).read_request() {
+        Err(ReadStreamError::BodyTooLarge) => {}
+        other => panic!(
+          "Expected too large body for {:?}, got: {:?}",
+          req_str, other
+        ),
+      }
+    }
+
+    for coding in &["gzip, chunked", "gzip", "chunked, chunked"] {
+      let req_str = format!(
+        "PUT / HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n0\r\n\r\n",
+        coding
+      );
+      match reader(&req_str).read_request() {
+        Err(ReadStreamError::UnsupportedCoding) => {}
+        other => panic!(
+          "Expected unsupported coding for {:?}, got: {:?}",
+          coding, other
+        ),
+      }
+    }
+  }
+
+  #[test]
+  fn timeout_on_incomplete_head() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind listener");
+    let address = listener.local_addr().expect("Unable to get address");
+    let client = thread::spawn(move || {
+      let mut stream = TcpStream::connect(address).expect("Unable to connect");
+      let _ = ::std::io::Write::write_all(&mut stream, b"GET / HTTP/1.1\r\n");
+      thread::sleep(Duration::from_millis(500));
+    });
+
+    let (stream, _) = listener.accept().expect("Unable to accept connection");
+    let timeouts = Timeouts {
+      head: Duration::from_millis(100),
+      ..Timeouts::default()
+    };
+    match RequestReader::new(&stream, Limits::default(), timeouts).read_request() {
+      Err(ReadStreamError::Timeout(TimeoutKind::Head)) => {}
+      other => panic!("Expected timeout on head, got: {:?}", other),
+    }
+    let _ = client.join();
+  }
+
+  #[test]
+  fn body_timeout_is_for_every_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind listener");
+    let address = listener.local_addr().expect("Unable to get address");
+    let client = thread::spawn(move || {
+      let mut stream = TcpStream::connect(address).expect("Unable to connect");
+      stream
+        .write_all(b"PUT / HTTP/1.1\r\nContent-Length: 8\r\n\r\n")
+        .expect("Unable to send head");
+      for part in &[b"ab", b"cd", b"ef"] {
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(&part[..]).expect("Unable to send body");
+      }
+      thread::sleep(Duration::from_millis(400));
+    });
+
+    let (stream, _) = listener.accept().expect("Unable to accept connection");
+    let timeouts = Timeouts {
+      body: Duration::from_millis(200),
+      ..Timeouts::default()
+    };
+    let mut reader = RequestReader::new(&stream, Limits::default(), timeouts);
+    reader.read_head().expect("Unable to read head");
+    let mut body = [0; 8];
+    let mut read = 0;
+    // Longer than the body timeout in all, but never waiting for that long
+    while read < 6 {
+      read += reader
+        .read_body(&mut body[read..])
+        .expect("Unable to read body");
+    }
+    assert_eq!(b"abcdef", &body[..6]);
+    match reader.read_body(&mut body[6..]) {
+      Err(ReadStreamError::Timeout(TimeoutKind::Body)) => {}
+      other => panic!("Expected timeout on body, got: {:?}", other),
+    }
+    let _ = client.join();
+  }
+
+  #[test]
+  fn unread_bodies_are_skipped() {
+    let reader =
+      reader("POST /a HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello worldGET /b HTTP/1.1\r\n\r\n");
+    let reader = Arc::new(Mutex::new(reader));
+    lock(&reader).read_head().expect("Unable to read head");
+
+    let mut hello = [0; 5];
+    RequestBody::new(Arc::clone(&reader))
+      .read_exact(&mut hello)
+      .expect("Unable to read body");
+    assert_eq!(b"hello", &hello);
+    assert_eq!(
+      Ok(true),
+      lock(&reader).finish_body().map_err(|e| e.to_string())
+    );
+    assert_eq!(11, lock(&reader).body_read());
+    assert_eq!(
+      "GET /b HTTP/1.1\r\n\r\n",
+      lock(&reader)
+        .read_request()
+        .expect("Unable to read request")
+    );
+  }
+
+  #[test]
+  fn continue_is_sent_when_the_body_is_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind listener");
+    let address = listener.local_addr().expect("Unable to get address");
+    let client = thread::spawn(move || {
+      let mut stream = TcpStream::connect(address).expect("Unable to connect");
+      let head = b"PUT / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 4\r\n\r\n";
+      stream.write_all(head).expect("Unable to send head");
+      let mut interim = [0; 25];
+      stream
+        .read_exact(&mut interim)
+        .expect("Unable to read interim response");
+      stream.write_all(b"body").expect("Unable to send body");
+      interim
+    });
+
+    let (stream, _) = listener.accept().expect("Unable to accept connection");
+    let shared = Arc::new(Mutex::new(RequestReader::new(
+      stream,
+      Limits::default(),
+      Timeouts::default(),
+    )));
+    lock(&shared).read_head().expect("Unable to read head");
+    lock(&shared).expect_continue();
+
+    let mut body = String::new();
+    RequestBody::new(Arc::clone(&shared))
+      .read_to_string(&mut body)
+      .expect("Unable to read body");
+    assert_eq!("body", body);
+    assert_eq!(
+      &b"HTTP/1.1 100 Continue\r\n\r\n"[..],
+      &client.join().expect("Client failed")[..]
+    );
+
+    assert_eq!(
+      Ok(true),
+      lock(&shared).finish_body().map_err(|e| e.to_string())
+    );
+
+    // A body which was never asked for is not waited for
+    let mut reader = reader("PUT / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 4\r\n\r\n");
+    reader.read_head().expect("Unable to read head");
+    reader.expect_continue();
+    assert_eq!(Ok(false), reader.finish_body().map_err(|e| e.to_string()));
+  }
+}
@@ -0,0 +1,352 @@
+use std::{collections::BTreeMap, fs::DirEntry, io, path::{Path, PathBuf}, thread};
+use std::sync::{Mutex, MutexGuard, mpsc::Sender};
+use std::time::{Duration, SystemTime};
+
+use http::{self, content::Contentable};
+use handler::Handler;
+use middleware::Middleware;
+use utils;
+
+/// How often the document roots are checked for changes.
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// The files under a directory with the time they were
+/// last modified and their size.
+pub type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+pub fn snapshot(root: &Path) -> io::Result<Snapshot> {
+  let mut files = Snapshot::new();
+  utils::visit_dir(root, &mut |entry: DirEntry, _| {
+    let metadata = entry.metadata()?;
+    files.insert(entry.path(), (metadata.modified().ok(), metadata.len()));
+    Ok(())
+  })?;
+  Ok(files)
+}
+
+/// The number of files which were added, removed or changed
+/// between two snapshots of the same directory.
+pub fn count_changes(old: &Snapshot, new: &Snapshot) -> usize {
+  let removed = old.keys().filter(|path| !new.contains_key(*path)).count();
+  let added_or_changed = new
+    .iter()
+    .filter(|&(path, file)| old.get(path) != Some(file))
+    .count();
+  removed + added_or_changed
+}
+
+/// Checks the roots for changes twice a second, and calls
+/// `reload` with the index of every root whose files changed and
+/// the number of files which changed. Never returns.
+pub fn watch<F>(roots: Vec<PathBuf>, mut reload: F)
+where
+  F: FnMut(usize, usize),
+{
+  let mut snapshots: Vec<Option<Snapshot>> = roots.iter().map(|root| snapshot(root).ok()).collect();
+
+  loop {
+    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    for (i, root) in roots.iter().enumerate() {
+      // A root which cannot be read, such as while a directory is
+      // being moved, is checked again after the next interval
+      let current = match snapshot(root) {
+        Ok(current) => current,
+        Err(_) => continue,
+      };
+      let changes = match snapshots[i] {
+        Some(ref previous) => count_changes(previous, &current),
+        None => current.len(),
+      };
+      if changes > 0 {
+        reload(i, changes);
+      }
+      snapshots[i] = Some(current);
+    }
+  }
+}
+
+/// The path of the Server-Sent Events endpoint which
+/// pages listen on to know when to reload.
+pub const EVENTS_PATH: &str = "/__live_reload";
+
+/// Injected into HTML pages, reloads the page on every event.
+const SCRIPT: &str = concat!(
+  "<script>new EventSource(\"/__live_reload\")",
+  ".onmessage = function () { location.reload(); };</script>"
+);
+
+/// How often a comment is sent on the streams of events, so that
+/// the streams of pages which were closed are noticed and ended.
+pub const KEEP_ALIVE_SECS: u64 = 20;
+
+/// The most streams of events which are open at once. Each one
+/// is sent on a thread of its own.
+const MAX_STREAMS: usize = 32;
+
+/// Tells pages in the browser to reload when files change. It
+/// injects a script into HTML responses, and answers the script's
+/// requests for events with a stream which stays open, on which
+/// an event is sent for every change.
+///
+/// Every change has a number, which is sent as the id of the
+/// event. The browser sends the last id it saw when it connects
+/// again, so a change in between is not missed.
+pub struct LiveReload {
+  listeners: Mutex<Listeners>,
+}
+
+/// The number of changes, and the streams of the pages to tell.
+/// No streams are started once it is closed.
+struct Listeners {
+  changes: u64,
+  streams: Vec<Sender<Vec<u8>>>,
+  closed: bool,
+}
+
+impl Listeners {
+  /// Sends to every stream, and forgets the streams which ended.
+  fn send(&mut self, message: &str) {
+    self
+      .streams
+      .retain(|stream| stream.send(message.as_bytes().to_vec()).is_ok());
+  }
+}
+
+impl LiveReload {
+  pub fn new() -> LiveReload {
+    LiveReload {
+      listeners: Mutex::new(Listeners {
+        changes: 0,
+        streams: Vec::new(),
+        closed: false,
+      }),
+    }
+  }
+
+  /// Tells every page which is listening to reload.
+  pub fn notify(&self) {
+    let mut listeners = self.lock();
+    listeners.changes += 1;
+    let event = format!("id: {}\ndata: reload\n\n", listeners.changes);
+    listeners.send(&event);
+  }
+
+  /// Sends a comment to every page which is listening. Writing it
+  /// fails for pages which are closed, which ends their streams.
+  pub fn keep_alive(&self) {
+    self.lock().send(": keep-alive\n\n");
+  }
+
+  /// Ends every stream, and answers the requests for events which
+  /// come after with an error, such as while the server stops.
+  pub fn close(&self) {
+    let mut listeners = self.lock();
+    listeners.closed = true;
+    listeners.streams.clear();
+  }
+
+  fn lock<'a>(&'a self) -> MutexGuard<'a, Listeners> {
+    match self.listeners.lock() {
+      Ok(listeners) => listeners,
+      Err(poisoned) => poisoned.into_inner(),
+    }
+  }
+}
+
+impl Handler for LiveReload {
+  /// Answers with a stream of events, which starts with a `reload`
+  /// event if there is a change the page has not seen. A different
+  /// id, such as after a restart, is also a change.
+  fn handle(&self, request: &http::Request) -> http::Response {
+    let (stream, body) = http::Body::channel();
+    let mut listeners = self.lock();
+    // Streams of pages which were closed are only noticed when
+    // something is sent on them
+    if listeners.streams.len() >= MAX_STREAMS {
+      listeners.send(": keep-alive\n\n");
+    }
+    if listeners.closed || listeners.streams.len() >= MAX_STREAMS {
+      return http::Response::new(http::StatusCode::ServiceUnavailable, "");
+    }
+    let seen = request
+      .has_header("Last-Event-ID")
+      .and_then(|id| id.trim().parse().ok())
+      .unwrap_or(listeners.changes);
+
+    let mut event = format!("retry: 500\nid: {}\n", listeners.changes);
+    if listeners.changes != seen {
+      event.push_str("data: reload\n");
+    }
+    event.push('\n');
+    if stream.send(event.into_bytes()).is_ok() {
+      listeners.streams.push(stream);
+    }
+
+    let mut response = http::Response::with_body(http::StatusCode::OK, body);
+    response.add_header("Content-Type", "text/event-stream");
+    response.add_header("Cache-Control", "no-cache");
+    response
+  }
+}
+
+impl Middleware for LiveReload {
+  fn after(&self, _request: &http::Request, response: &mut http::Response) {
+    let is_html = response
+      .has_header("Content-Type")
+      .map_or(false, |content_type| content_type.starts_with("text/html"));
+    if !is_html {
+      return;
+    }
+    // A page which is sent from disk is not read into memory, so the
+    // script is sent after it, where browsers still run it
+    match response.take_body() {
+      Some(http::Body::Bytes(page)) => {
+        let position = script_position(&page);
+        let body = [&page[..position], SCRIPT.as_bytes(), &page[position..]].concat();
+        response.replace_body(http::Body::Bytes(body));
+      }
+      Some(body) => response.replace_body(body.followed_by(SCRIPT.as_bytes().to_vec())),
+      None => {
+        let body = inject_script(response.get_body());
+        response.set_body(body);
+      }
+    }
+  }
+}
+
+/// Adds the script before the closing body tag, or at
+/// the end of the page if there is none.
+fn inject_script(page: &str) -> String {
+  let position = script_position(page.as_bytes());
+  format!("{}{}{}", &page[..position], SCRIPT, &page[position..])
+}
+
+/// Where the script is added to a page.
+fn script_position(page: &[u8]) -> usize {
+  page
+    .windows(7)
+    .rposition(|window| window.eq_ignore_ascii_case(b"</body>"))
+    .unwrap_or_else(|| page.len())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn changes_between_snapshots() {
+    let file = |secs, len| {
+      (
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        len,
+      )
+    };
+    let mut old = Snapshot::new();
+    old.insert(PathBuf::from("a.html"), file(1, 10));
+    old.insert(PathBuf::from("b.html"), file(1, 10));
+    old.insert(PathBuf::from("c.html"), file(1, 10));
+
+    let mut new = old.clone();
+    assert_eq!(0, count_changes(&old, &new));
+
+    new.remove(Path::new("a.html"));
+    new.insert(PathBuf::from("b.html"), file(2, 10));
+    new.insert(PathBuf::from("d.html"), file(2, 5));
+    assert_eq!(3, count_changes(&old, &new));
+  }
+
+  #[test]
+  fn script_is_injected_into_html() {
+    assert_eq!(
+      format!("<html><BODY>page{}</BODY></html>", SCRIPT),
+      inject_script("<html><BODY>page</BODY></html>")
+    );
+    assert_eq!(format!("page{}", SCRIPT), inject_script("page"));
+
+    let live_reload = LiveReload::new();
+    let request = http::Request::new(http::RequestMethod::GET, "/");
+    let mut css = http::Response::new(http::StatusCode::OK, "body {}");
+    css.add_header("Content-Type", "text/css");
+    live_reload.after(&request, &mut css);
+    assert_eq!("body {}", css.get_body());
+
+    let page = b"<html><body>page</body></html>".to_vec();
+    let mut html = http::Response::with_body(http::StatusCode::OK, http::Body::Bytes(page));
+    html.add_header("Content-Type", "text/html");
+    live_reload.after(&request, &mut html);
+    assert_eq!("", html.get_body());
+    match html.take_body() {
+      Some(http::Body::Bytes(bytes)) => {
+        assert_eq!(
+          format!("<html><body>page{}</body></html>", SCRIPT).into_bytes(),
+          bytes
+        )
+      }
+      other => panic!("Expected the page with the script, got: {:?}", other),
+    }
+
+    let page = ::std::io::Cursor::new(b"page".to_vec());
+    let mut html = http::Response::with_body(
+      http::StatusCode::OK,
+      http::Body::Reader(Box::new(page), Some(4)),
+    );
+    html.add_header("Content-Type", "text/html");
+    live_reload.after(&request, &mut html);
+    assert_eq!(Some(4 + SCRIPT.len() as u64), html.content_length());
+  }
+
+  #[test]
+  fn events_for_changes_which_were_not_seen() {
+    let live_reload = LiveReload::new();
+    live_reload.notify();
+    let mut request = http::Request::new(http::RequestMethod::GET, EVENTS_PATH);
+    request.add_header("Last-Event-ID", "0");
+    let events = match live_reload.handle(&request).take_body() {
+      Some(http::Body::Channel(events)) => events,
+      other => panic!("Expected a stream of events, got: {:?}", other),
+    };
+    let next =
+      || String::from_utf8(events.try_recv().expect("No event was sent")).expect("Invalid event");
+    assert_eq!("retry: 500\nid: 1\ndata: reload\n\n", next());
+
+    live_reload.notify();
+    assert_eq!("id: 2\ndata: reload\n\n", next());
+    live_reload.keep_alive();
+    assert_eq!(": keep-alive\n\n", next());
+
+    drop(events);
+    live_reload.keep_alive();
+    assert!(live_reload.lock().streams.is_empty());
+  }
+
+  #[test]
+  fn number_of_streams_is_bounded() {
+    let live_reload = LiveReload::new();
+    let request = http::Request::new(http::RequestMethod::GET, EVENTS_PATH);
+    let mut streams: Vec<_> = (0..MAX_STREAMS)
+      .map(|_| live_reload.handle(&request).take_body())
+      .collect();
+    let res = live_reload.handle(&request);
+    assert_eq!(http::StatusCode::ServiceUnavailable, res.status_code());
+
+    streams.pop();
+    assert_eq!(
+      http::StatusCode::OK,
+      live_reload.handle(&request).status_code()
+    );
+  }
+
+  #[test]
+  fn closing_ends_the_streams() {
+    let live_reload = LiveReload::new();
+    let request = http::Request::new(http::RequestMethod::GET, EVENTS_PATH);
+    let events = match live_reload.handle(&request).take_body() {
+      Some(http::Body::Channel(events)) => events,
+      other => panic!("Expected a stream of events, got: {:?}", other),
+    };
+    live_reload.close();
+    assert_eq!(1, events.iter().count());
+    let res = live_reload.handle(&request);
+    assert_eq!(http::StatusCode::ServiceUnavailable, res.status_code());
+  }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use errors::RouteError;
+
+/// The values captured by the parameters and the wildcard
+/// of a pattern, in the order they appear in the pattern.
+pub type Params = Vec<(String, String)>;
+
+/// Maps path patterns to values. A pattern is made of segments
+/// separated by `/`, where a segment is either static, a parameter
+/// such as `:id` which matches one non-empty segment, or a wildcard
+/// such as `*rest` which matches the rest of the path. A pattern
+/// ending in `/?` matches both with and without the trailing slash.
+///
+/// Static segments are tried first, then parameters and then
+/// wildcards, so `/users/me` wins over `/users/:id`, which wins
+/// over `/users/*rest`.
+#[derive(Clone)]
+pub struct RouteTree<T> {
+  root: Node<T>,
+}
+
+#[derive(Clone)]
+struct Node<T> {
+  value: Option<T>,
+  statics: HashMap<String, Node<T>>,
+  param: Option<(String, Box<Node<T>>)>,
+  wildcard: Option<(String, T)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Segment<'a> {
+  Static(&'a str),
+  Param(&'a str),
+  Wildcard(&'a str),
+}
+
+impl<T: Default> RouteTree<T> {
+  pub fn new() -> RouteTree<T> {
+    RouteTree { root: Node::new() }
+  }
+
+  /// Calls `update` with the value of every path the pattern
+  /// describes, adding default values where there are none.
+  pub fn update<F>(&mut self, pattern: &str, mut update: F) -> Result<(), RouteError>
+  where
+    F: FnMut(&mut T) -> Result<(), RouteError>,
+  {
+    for segments in parse_pattern(pattern)? {
+      update(self.root.entry(pattern, &segments)?)?;
+    }
+    Ok(())
+  }
+
+  /// Finds the value of the pattern which matches the path
+  /// best, together with the values captured from the path.
+  pub fn find(&self, path: &str) -> Option<(&T, Params)> {
+    let segments: Vec<&str> = split_path(path).collect();
+    let mut params = Params::new();
+    self
+      .root
+      .find(&segments, &mut params)
+      .map(|value| (value, params))
+  }
+
+  /// All the values in the tree.
+  pub fn values(&self) -> Vec<&T> {
+    let mut values = Vec::new();
+    self.root.collect_values(&mut values);
+    values
+  }
+
+  /// Every path in the tree with its value, sorted by path.
+  /// Parameters and wildcards are written as in patterns.
+  pub fn paths(&self) -> Vec<(String, &T)> {
+    let mut paths = Vec::new();
+    self.root.collect_paths(&mut Vec::new(), &mut paths);
+    paths.sort_by(|a, b| a.0.cmp(&b.0));
+    paths
+  }
+}
+
+impl<T: Default> Node<T> {
+  fn new() -> Node<T> {
+    Node {
+      value: None,
+      statics: HashMap::new(),
+      param: None,
+      wildcard: None,
+    }
+  }
+
+  fn entry(&mut self, pattern: &str, segments: &[Segment]) -> Result<&mut T, RouteError> {
+    let (first, rest) = match segments.split_first() {
+      Some(split) => split,
+      None => return Ok(self.value.get_or_insert_with(T::default)),
+    };
+
+    match *first {
+      Segment::Static(name) => self
+        .statics
+        .entry(name.to_string())
+        .or_insert_with(Node::new)
+        .entry(pattern, rest),
+      Segment::Param(name) => {
+        let &mut (ref existing, ref mut child) = self
+          .param
+          .get_or_insert_with(|| (name.to_string(), Box::new(Node::new())));
+        if existing != name {
+          return Err(RouteError::conflict(
+            pattern,
+            format!("parameter ':{}' is already named ':{}'", name, existing),
+          ));
+        }
+        child.entry(pattern, rest)
+      }
+      Segment::Wildcard(name) => {
+        let &mut (ref existing, ref mut value) = self
+          .wildcard
+          .get_or_insert_with(|| (name.to_string(), T::default()));
+        if existing != name {
+          return Err(RouteError::conflict(
+            pattern,
+            format!("wildcard '*{}' is already named '*{}'", name, existing),
+          ));
+        }
+        Ok(value)
+      }
+    }
+  }
+
+  fn collect_values<'a>(&'a self, values: &mut Vec<&'a T>) {
+    values.extend(self.value.as_ref());
+    for child in self.statics.values() {
+      child.collect_values(values);
+    }
+    if let Some((_, ref child)) = self.param {
+      child.collect_values(values);
+    }
+    values.extend(self.wildcard.as_ref().map(|&(_, ref value)| value));
+  }
+
+  fn collect_paths<'a>(&'a self, segments: &mut Vec<String>, paths: &mut Vec<(String, &'a T)>) {
+    let path = format!("/{}", segments.join("/"));
+    if let Some(ref value) = self.value {
+      paths.push((path.clone(), value));
+    }
+    for (name, child) in &self.statics {
+      segments.push(name.clone());
+      child.collect_paths(segments, paths);
+      segments.pop();
+    }
+    if let Some((ref name, ref child)) = self.param {
+      segments.push(format!(":{}", name));
+      child.collect_paths(segments, paths);
+      segments.pop();
+    }
+    if let Some((ref name, ref value)) = self.wildcard {
+      segments.push(format!("*{}", name));
+      paths.push((format!("/{}", segments.join("/")), value));
+      segments.pop();
+    }
+  }
+
+  fn find(&self, segments: &[&str], params: &mut Params) -> Option<&T> {
+    let (first, rest) = match segments.split_first() {
+      Some(split) => split,
+      None => return self.value.as_ref(),
+    };
+
+    if let Some(value) = self
+      .statics
+      .get(*first)
+      .and_then(|child| child.find(rest, params))
+    {
+      return Some(value);
+    }
+
+    if let Some((ref name, ref child)) = self.param {
+      if !first.is_empty() {
+        params.push((name.clone(), first.to_string()));
+        if let Some(value) = child.find(rest, params) {
+          return Some(value);
+        }
+        params.pop();
+      }
+    }
+
+    self.wildcard.as_ref().map(|&(ref name, ref value)| {
+      params.push((name.clone(), segments.join("/")));
+      value
+    })
+  }
+}
+
+/// Splits a path into its segments. The root is a single empty
+/// segment, and a trailing slash gives an empty last segment.
+fn split_path<'a>(path: &'a str) -> ::std::str::Split<'a, char> {
+  path.trim_left_matches('/').split('/')
+}
+
+/// Parses a pattern into the segments of every path it describes.
+fn parse_pattern<'a>(pattern: &'a str) -> Result<Vec<Vec<Segment<'a>>>, RouteError> {
+  if !pattern.starts_with('/') {
+    return Err(RouteError::invalid(
+      pattern,
+      "a pattern has to start with '/'",
+    ));
+  }
+
+  let (base, optional_slash) = if pattern.ends_with("/?") {
+    (&pattern[..pattern.len() - 2], true)
+  } else {
+    (pattern, false)
+  };
+
+  let mut segments = Vec::new();
+  let parts: Vec<&str> = split_path(base).collect();
+  for (i, part) in parts.iter().enumerate() {
+    let segment = if part.starts_with(':') {
+      Segment::Param(&part[1..])
+    } else if part.starts_with('*') {
+      if i + 1 != parts.len() || optional_slash {
+        return Err(RouteError::invalid(
+          pattern,
+          "a wildcard has to be the last segment",
+        ));
+      }
+      Segment::Wildcard(&part[1..])
+    } else {
+      Segment::Static(part)
+    };
+
+    match segment {
+      Segment::Param(name) | Segment::Wildcard(name) if name.is_empty() => {
+        return Err(RouteError::invalid(
+          pattern,
+          "parameters and wildcards need a name",
+        ))
+      }
+      _ => segments.push(segment),
+    }
+  }
+
+  if !optional_slash {
+    return Ok(vec![segments]);
+  }
+  // `/?` on its own is the root, which always has the slash
+  if base.is_empty() {
+    return Ok(vec![vec![Segment::Static("")]]);
+  }
+  let mut with_slash = segments.clone();
+  with_slash.push(Segment::Static(""));
+  Ok(vec![segments, with_slash])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tree(patterns: &[&str]) -> RouteTree<String> {
+    let mut tree = RouteTree::new();
+    for &pattern in patterns {
+      tree
+        .update(pattern, |value| Ok(*value = pattern.to_string()))
+        .expect("Unable to add pattern");
+    }
+    tree
+  }
+
+  fn find(tree: &RouteTree<String>, path: &str) -> Option<(String, Params)> {
+    tree
+      .find(path)
+      .map(|(value, params)| (value.clone(), params))
+  }
+
+  fn params(pairs: &[(&str, &str)]) -> Params {
+    pairs
+      .iter()
+      .map(|&(name, value)| (name.to_string(), value.to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn static_then_param_then_wildcard() {
+    let tree = tree(&[
+      "/",
+      "/users/me",
+      "/users/:id",
+      "/users/:id/posts",
+      "/users/*rest",
+    ]);
+
+    let cases = vec![
+      ("/", "/", params(&[])),
+      ("/users/me", "/users/me", params(&[])),
+      ("/users/42", "/users/:id", params(&[("id", "42")])),
+      (
+        "/users/42/posts",
+        "/users/:id/posts",
+        params(&[("id", "42")]),
+      ),
+      (
+        "/users/42/likes",
+        "/users/*rest",
+        params(&[("rest", "42/likes")]),
+      ),
+      ("/users/", "/users/*rest", params(&[("rest", "")])),
+    ];
+    for (path, pattern, captured) in cases {
+      assert_eq!(
+        Some((pattern.to_string(), captured)),
+        find(&tree, path),
+        "Wrong match for path {}",
+        path
+      );
+    }
+
+    assert_eq!(None, find(&tree, "/users"));
+    assert_eq!(None, find(&tree, "/about"));
+    assert_eq!(5, tree.values().len());
+    let paths: Vec<String> = tree.paths().into_iter().map(|(path, _)| path).collect();
+    assert_eq!(
+      vec![
+        "/",
+        "/users/*rest",
+        "/users/:id",
+        "/users/:id/posts",
+        "/users/me"
+      ],
+      paths
+    );
+  }
+
+  #[test]
+  fn optional_trailing_slash() {
+    let tree = tree(&["/about/?", "/files/:name/?"]);
+
+    assert!(find(&tree, "/about").is_some());
+    assert!(find(&tree, "/about/").is_some());
+    assert_eq!(
+      Some(params(&[("name", "a")])),
+      find(&tree, "/files/a/").map(|(_, p)| p)
+    );
+    assert_eq!(None, find(&tree, "/about/us"));
+  }
+
+  #[test]
+  fn invalid_and_conflicting_patterns() {
+    let invalid = vec!["users", "/files/*rest/more", "/users/:", "/files/*rest/?"];
+    for pattern in invalid {
+      let mut tree: RouteTree<String> = RouteTree::new();
+      assert!(
+        tree.update(pattern, |_| Ok(())).is_err(),
+        "Pattern should be invalid: {}",
+        pattern
+      );
+    }
+
+    let mut tree = tree(&["/users/:id", "/files/*rest"]);
+    assert!(tree.update("/users/:name/posts", |_| Ok(())).is_err());
+    assert!(tree.update("/files/*path", |_| Ok(())).is_err());
+    assert!(tree.update("/users/:id/posts", |_| Ok(())).is_ok());
+  }
+}
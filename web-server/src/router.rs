@@ -1,54 +1,850 @@
-use std::{collections::HashMap, fs::{DirEntry, File}, io::Read, path::{Path, PathBuf}};
+use std::{path::Path, sync::Arc};
 
-use http;
+use http::{self, content::Contentable};
+use autoindex;
+use config::{AuthRule, LogLevel, Redirect, SiteConfig};
+use errors::RouteError;
+use files::{FileCache, FileContents, StaticFiles};
+use handler::Handler;
+use logger::Context;
+use metrics::Metrics;
+use middleware::{self, Pipeline};
+use route::{Params, RouteTree};
 use utils;
 
+/// The handlers at a path. A path can have both a static file
+/// and handlers, in which case the handlers are used for the
+/// methods they are added for.
+#[derive(Clone, Default)]
+pub struct Endpoint {
+  handlers: Vec<(http::RequestMethod, Arc<Handler>)>,
+}
+
+/// The order methods are listed in the `Allow` header.
+const METHODS: [http::RequestMethod; 7] = [
+  http::RequestMethod::GET,
+  http::RequestMethod::HEAD,
+  http::RequestMethod::POST,
+  http::RequestMethod::PUT,
+  http::RequestMethod::PATCH,
+  http::RequestMethod::DELETE,
+  http::RequestMethod::OPTIONS,
+];
+
+impl Endpoint {
+  /// Whether the endpoint answers requests with the method, when
+  /// there is a static file at its path or not. Static files answer
+  /// `GET`, every endpoint which answers `GET` also answers `HEAD`,
+  /// and every endpoint answers `OPTIONS` with the methods it allows.
+  fn allows(&self, method: http::RequestMethod, has_file: bool) -> bool {
+    match method {
+      http::RequestMethod::OPTIONS => true,
+      http::RequestMethod::GET | http::RequestMethod::HEAD if has_file => true,
+      _ => self.handler(method).is_some(),
+    }
+  }
+
+  /// The handler for the method. A `HEAD` request is answered by
+  /// the `GET` handler when there is no `HEAD` handler, and the
+  /// body is removed before the response is sent.
+  fn handler(&self, method: http::RequestMethod) -> Option<&Arc<Handler>> {
+    let find = |method| {
+      self
+        .handlers
+        .iter()
+        .find(|&&(m, _)| m == method)
+        .map(|&(_, ref handler)| handler)
+    };
+    match method {
+      http::RequestMethod::HEAD => find(method).or_else(|| find(http::RequestMethod::GET)),
+      _ => find(method),
+    }
+  }
+}
+
+/// The endpoints of a site by their path pattern.
+pub type Routes = RouteTree<Endpoint>;
+
+/// Adds a handler for requests with the method to every path
+/// which matches the pattern. Two handlers for the same method
+/// and pattern is a conflict.
+pub fn add_route(
+  routes: &mut Routes,
+  method: http::RequestMethod,
+  pattern: &str,
+  handler: Arc<Handler>,
+) -> Result<(), RouteError> {
+  routes.update(pattern, |endpoint| {
+    if endpoint.handlers.iter().any(|&(m, _)| m == method) {
+      return Err(RouteError::conflict(
+        pattern,
+        format!("{} is already handled", method),
+      ));
+    }
+    endpoint.handlers.push((method, Arc::clone(&handler)));
+    Ok(())
+  })
+}
+
+/// The patterns which have handlers, each with the methods it
+/// answers, such as `GET, HEAD /users/:id`.
+pub fn describe_routes(routes: &Routes) -> Vec<String> {
+  routes
+    .paths()
+    .into_iter()
+    .filter(|&(_, endpoint)| !endpoint.handlers.is_empty())
+    .map(|(path, endpoint)| {
+      let methods: Vec<String> = METHODS
+        .iter()
+        .filter(|&&method| endpoint.handler(method).is_some())
+        .map(|method| method.to_string())
+        .collect();
+      format!("{} {}", methods.join(", "), path)
+    })
+    .collect()
+}
+
+/// Answers the requests to a site. Static files are served at
+/// their own path, and handlers at the paths matching their
+/// pattern. See `RouteTree` for how a path is matched.
+///
+/// A path which only serves something with or without a trailing
+/// `/` is redirected to the path which does.
 pub struct Router {
-  paths: HashMap<String, String>,
+  routes: Routes,
+  files: Option<StaticFiles>,
+  slash_redirect: http::StatusCode,
+  pipeline: Pipeline,
+  metrics: Arc<Metrics>,
+  headers: Vec<(String, String)>,
+  redirects: Vec<Redirect>,
+  auth: Vec<AuthRule>,
 }
 
 impl Router {
-  pub fn new(dir_path: &Path) -> Router {
-    let mut paths: HashMap<String, String> = HashMap::new();
-
-    utils::visit_dir(dir_path, &mut |entry: DirEntry, dir_depth: usize| {
-      let file_path = entry.path();
-      let mut file = File::open(&file_path).expect(&format!(
-        "Unable to open file at: {}",
-        file_path.to_string_lossy()
-      ));
+  /// Creates a router which serves the routes and the files in
+  /// the document root of the site. Files are read when they are
+  /// requested through the cache, which can be shared with the
+  /// routers of other sites. See `StaticFiles` for the paths the
+  /// files are served at.
+  pub fn with_routes(site: &SiteConfig, routes: Routes, cache: Arc<FileCache>) -> Router {
+    // Paths are matched in the form requests are normalized to
+    let mut redirects = site.redirects.clone();
+    for redirect in &mut redirects {
+      redirect.from = utils::normalize_path(&redirect.from);
+    }
+    let mut auth = site.auth.clone();
+    for rule in &mut auth {
+      rule.prefix = utils::normalize_path(&rule.prefix);
+    }
+    Router {
+      files: Some(StaticFiles::new(site, cache)),
+      slash_redirect: site.slash_redirect,
+      headers: site.headers.clone(),
+      redirects,
+      auth,
+      ..Router::from_routes(routes)
+    }
+  }
 
-      let mut file_contents = String::new();
-      let _ = file.read_to_string(&mut file_contents);
+  /// Creates a router which only serves the routes.
+  pub fn from_routes(routes: Routes) -> Router {
+    Router {
+      routes,
+      files: None,
+      slash_redirect: http::StatusCode::MovedPermanently,
+      pipeline: Pipeline::new(),
+      metrics: Arc::new(Metrics::new()),
+      headers: Vec::new(),
+      redirects: Vec::new(),
+      auth: Vec::new(),
+    }
+  }
+
+  /// Records the lookups in the route table and
+  /// the file cache in the metrics.
+  pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Router {
+    self.metrics = metrics;
+    self
+  }
 
-      let uri = utils::turn_path_into_uri(&file_path, dir_depth, false)
-        .expect("Unable to turn filepath into uri");
+  /// Runs requests through the middleware of the pipeline
+  /// before they are routed.
+  pub fn with_pipeline(mut self, pipeline: Pipeline) -> Router {
+    self.pipeline = pipeline;
+    self
+  }
 
-      paths.insert(uri, file_contents);
+  /// The path of the request is normalized first, so that the
+  /// middleware, redirects, auth rules and files all see the
+  /// same path for paths which are written differently.
+  pub fn handle_request(&self, mut request: http::Request, context: &Context) -> http::Response {
+    let uri = match request.query() {
+      Some(query) => format!("{}?{}", utils::normalize_path(request.path()), query),
+      None => utils::normalize_path(request.path()),
+    };
+    request.set_uri(uri);
+    let accept = request
+      .has_header("Accept")
+      .map(|accept| accept.to_string());
+    let mut response = self.pipeline.handle(request, |request| {
+      let mut response = self.route(request, context);
+      for &(ref name, ref value) in &self.headers {
+        response.add_header(name.clone(), value.clone());
+      }
+      response
     });
-    Router { paths }
+    // Middleware can answer with an error too, so the body is added last
+    self.add_error_body(
+      accept.as_ref().map(|accept| accept.as_str()),
+      &mut response,
+      context,
+    );
+    response
+  }
+
+  fn route(&self, request: &mut http::Request, context: &Context) -> http::Response {
+    if let Some(redirect) = self.redirects.iter().find(|r| r.from == request.path()) {
+      let mut response = http::Response::new(redirect.status, "");
+      response.add_header("Location", redirect.to.as_str());
+      return response;
+    }
+
+    let rule = self
+      .auth
+      .iter()
+      .find(|rule| middleware::has_prefix(request.path(), &rule.prefix));
+    if let Some(rule) = rule {
+      if !is_authorized(rule, request) {
+        let mut response = http::Response::new(http::StatusCode::Unauthorized, "");
+        response.add_header(
+          "WWW-Authenticate".to_string(),
+          format!("Basic realm=\"{}\"", rule.realm),
+        );
+        return response;
+      }
+    }
+
+    // `OPTIONS *` asks about the server rather than a path (RFC 7231, 4.3.7)
+    if request.method() == http::RequestMethod::OPTIONS && request.uri() == "*" {
+      let endpoints = self.routes.values();
+      let has_files = self.files.is_some();
+      return allow_response(http::StatusCode::NoContent, |method| {
+        Endpoint::default().allows(method, has_files)
+          || endpoints
+            .iter()
+            .any(|endpoint| endpoint.allows(method, false))
+      });
+    }
+
+    let found = self.routes.find(request.path());
+    self.metrics.record_route_lookup(found.is_some());
+    let file_path = self
+      .files
+      .as_ref()
+      .and_then(|files| files.resolve(request.path()));
+    let listing = match file_path {
+      Some(_) => None,
+      None => self
+        .files
+        .as_ref()
+        .and_then(|files| files.resolve_listing(request.path())),
+    };
+    // A listed directory is answered like a file
+    let has_file = file_path.is_some() || listing.is_some();
+    let no_endpoint = Endpoint::default();
+    let (endpoint, params) = match found {
+      Some(found) => found,
+      None if has_file => (&no_endpoint, Params::new()),
+      None => return self.redirect_to_canonical(request),
+    };
+
+    if let Some(handler) = endpoint.handler(request.method()) {
+      for (name, value) in params {
+        request.set_param(name, value);
+      }
+      return handler.handle(request);
+    }
+
+    match (request.method(), file_path, listing) {
+      (http::RequestMethod::GET, Some(path), _) | (http::RequestMethod::HEAD, Some(path), _) => {
+        self.serve_file(&path, context)
+      }
+      (http::RequestMethod::GET, _, Some(dir)) | (http::RequestMethod::HEAD, _, Some(dir)) => {
+        self.serve_listing(request, &dir, context)
+      }
+      (http::RequestMethod::OPTIONS, _, _) => {
+        allow_response(http::StatusCode::NoContent, |method| {
+          endpoint.allows(method, has_file)
+        })
+      }
+      (_, _, _) => allow_response(http::StatusCode::MethodNotAllowed, |method| {
+        endpoint.allows(method, has_file)
+      }),
+    }
+  }
+
+  fn serve_file(&self, path: &Path, context: &Context) -> http::Response {
+    let read = self.files.as_ref().map(|files| files.read(path));
+    match read {
+      Some(Ok((file, lookup))) => {
+        self.metrics.record_file_cache(lookup.name());
+        let mut response = match file.contents {
+          FileContents::Memory(bytes) => {
+            http::Response::with_body(http::StatusCode::OK, http::Body::Bytes(bytes.to_vec()))
+          }
+          FileContents::Disk(file, length) => {
+            http::Response::with_body(http::StatusCode::OK, http::Body::File(file, length))
+          }
+        };
+        response.add_header("Content-Type", file.content_type.as_str());
+        response
+      }
+      Some(Err(e)) => {
+        log!(
+          LogLevel::Error,
+          "file",
+          context,
+          "unable to read {}: {}",
+          path.display(),
+          e
+        );
+        http::Response::new(http::StatusCode::InternalServerError, "")
+      }
+      None => http::Response::new(http::StatusCode::NotFound, ""),
+    }
   }
 
-  pub fn handle_request(&self, request: http::Request) -> http::Response {
-    if let Some(res_string) = self.paths.get(request.uri()) {
-      http::Response::new(http::StatusCode::OK, res_string.clone())
+  /// Gives an error response without a body the body for its status:
+  /// JSON when the client prefers it, and otherwise the error document
+  /// of the site if there is one, or the reason phrase.
+  pub fn add_error_body(
+    &self,
+    accept: Option<&str>,
+    response: &mut http::Response,
+    context: &Context,
+  ) {
+    let status_code = response.status_code();
+    if (status_code as u16) < 400 || response.content_length() != Some(0) {
+      return;
+    }
+    let reason = status_code.to_reason_phrase().to_string();
+    let (body, content_type) = if accept.map_or(false, utils::prefers_json) {
+      let json = format!(
+        "{{\"status\":{},\"error\":{}}}\n",
+        status_code as u16,
+        utils::json_string(&reason)
+      );
+      (json, "application/json".to_string())
     } else {
-      http::Response::new(http::StatusCode::NotFound, "".to_string())
+      match self
+        .files
+        .as_ref()
+        .and_then(|files| files.error_page(status_code as u16, context))
+      {
+        Some((page, content_type)) => (page, content_type),
+        None => (
+          format!("{}\n", reason),
+          "text/plain; charset=utf-8".to_string(),
+        ),
+      }
+    };
+    response.set_body(body);
+    response.add_header("Content-Type".to_string(), content_type);
+  }
+
+  /// Redirects to the path with or without the trailing `/`, keeping
+  /// the query, when that path serves something and the path of the
+  /// request does not.
+  fn redirect_to_canonical(&self, request: &http::Request) -> http::Response {
+    let canonical = self
+      .files
+      .as_ref()
+      .and_then(|files| files.canonical_path(request.path()));
+    match canonical {
+      Some(mut location) => {
+        if let Some(query) = request.query() {
+          location.push('?');
+          location.push_str(query);
+        }
+        let mut response = http::Response::new(self.slash_redirect, "");
+        response.add_header("Location".to_string(), location);
+        response
+      }
+      None => http::Response::new(http::StatusCode::NotFound, ""),
     }
   }
+
+  fn serve_listing(
+    &self,
+    request: &http::Request,
+    dir: &Path,
+    context: &Context,
+  ) -> http::Response {
+    let list = self.files.as_ref().map(|files| files.list(dir));
+    match list {
+      Some(Ok(entries)) => autoindex::respond(request, entries),
+      Some(Err(e)) => {
+        log!(
+          LogLevel::Error,
+          "file",
+          context,
+          "unable to list {}: {}",
+          dir.display(),
+          e
+        );
+        http::Response::new(http::StatusCode::InternalServerError, "")
+      }
+      None => http::Response::new(http::StatusCode::NotFound, ""),
+    }
+  }
+}
+
+/// A response without a body which lists the allowed methods
+/// in the `Allow` header.
+fn allow_response<F>(status_code: http::StatusCode, allows: F) -> http::Response
+where
+  F: Fn(http::RequestMethod) -> bool,
+{
+  let allowed: Vec<String> = METHODS
+    .iter()
+    .filter(|&&method| allows(method))
+    .map(|method| method.to_string())
+    .collect();
+  let mut response = http::Response::new(status_code, "");
+  response.add_header("Allow".to_string(), allowed.join(", "));
+  response
+}
+
+/// Checks the basic credentials in the `Authorization`
+/// header against the users of the rule.
+fn is_authorized(rule: &AuthRule, request: &http::Request) -> bool {
+  let credentials = request
+    .has_header("Authorization")
+    .and_then(|value| {
+      let mut parts = value.splitn(2, ' ');
+      match (parts.next(), parts.next()) {
+        (Some(scheme), Some(encoded)) if scheme.eq_ignore_ascii_case("basic") => {
+          utils::decode_base64(encoded.trim())
+        }
+        _ => None,
+      }
+    })
+    .and_then(|decoded| String::from_utf8(decoded).ok());
+
+  let credentials = match credentials {
+    Some(credentials) => credentials,
+    None => return false,
+  };
+  let mut parts = credentials.splitn(2, ':');
+  let (name, password) = match (parts.next(), parts.next()) {
+    (Some(name), Some(password)) => (name, password),
+    _ => return false,
+  };
+
+  // Every user is compared in constant time, so that the time taken
+  // does not tell which users exist or how much of a password is right
+  rule.users.iter().fold(false, |authorized, user| {
+    let is_user = utils::constant_time_eq(user.name.as_bytes(), name.as_bytes());
+    let is_password = user.has_password(password);
+    authorized | (is_user & is_password)
+  })
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use std::{convert::TryFrom, env, fs, path::PathBuf, process};
+  use config::{CacheConfig, User};
+
+  fn request(req_str: &str) -> http::Request {
+    http::Request::try_from(req_str.to_string()).expect("Unable to parse request")
+  }
+
+  fn cache() -> Arc<FileCache> {
+    Arc::new(FileCache::new(CacheConfig::default()))
+  }
+
+  fn handle(router: &Router, req_str: &str) -> http::Response {
+    router.handle_request(request(req_str), &Context::default())
+  }
+
+  /// The body of a response, whether it is a string or bytes.
+  fn body(mut response: http::Response) -> String {
+    match response.take_body() {
+      Some(http::Body::Bytes(bytes)) => String::from_utf8(bytes).expect("Body is not text"),
+      Some(other) => panic!("Expected a body in memory, got: {:?}", other),
+      None => response.get_body().to_string(),
+    }
+  }
+
+  /// A root with files for a test, which is removed when it is dropped.
+  struct TempRoot(PathBuf);
+
+  impl TempRoot {
+    /// Paths which end in `/` are created as empty directories.
+    fn new(name: &str, files: &[(&str, &str)]) -> TempRoot {
+      let root = TempRoot(env::temp_dir().join(format!("web-server-{}-{}", name, process::id())));
+      let _ = fs::remove_dir_all(&root.0);
+      fs::create_dir_all(&root.0).expect("Unable to create root");
+      for &(path, contents) in files {
+        if path.ends_with('/') {
+          fs::create_dir_all(root.0.join(path)).expect("Unable to create dir");
+        } else {
+          root.write(path, contents);
+        }
+      }
+      root
+    }
+
+    fn write(&self, path: &str, contents: &str) {
+      let path = self.0.join(path);
+      if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).expect("Unable to create dir");
+      }
+      fs::write(path, contents).expect("Unable to write file");
+    }
+
+    fn remove(&self, path: &str) {
+      fs::remove_file(self.0.join(path)).expect("Unable to remove file");
+    }
+  }
+
+  impl Drop for TempRoot {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  #[test]
+  fn redirect_and_auth_from_site_config() {
+    let mut site = SiteConfig::new("./html/");
+    site.redirects.push(Redirect {
+      from: "/old/".to_string(),
+      to: "/about/".to_string(),
+      status: http::StatusCode::MovedPermanently,
+    });
+    site.auth.push(AuthRule {
+      prefix: "/about/us/".to_string(),
+      realm: "Us".to_string(),
+      users: vec![User::new("admin", "salt", "secret")],
+    });
+    site.headers.push(("X-Test".to_string(), "1".to_string()));
+    let router = Router::with_routes(&site, Routes::new(), cache());
+
+    let res = handle(&router, "GET /old/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(Some("/about/"), res.has_header("Location"));
+    assert_eq!(Some("1"), res.has_header("X-Test"));
+
+    let res = handle(
+      &router,
+      "GET /about/us/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(
+      Some("Basic realm=\"Us\""),
+      res.has_header("WWW-Authenticate")
+    );
+
+    // Escaped paths name the same files, so they are behind the same rule
+    for path in &[
+      "/about/%75s/",
+      "/%61bout/us/index.html",
+      "/about/u%73/?a=%62",
+    ] {
+      let req_str = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+      let res = handle(&router, &req_str);
+      assert_eq!(
+        http::StatusCode::Unauthorized,
+        res.status_code(),
+        "{} was not behind the rule",
+        path
+      );
+    }
+    let res = handle(&router, "GET /%6Fld/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(Some("/about/"), res.has_header("Location"));
+    let res = handle(&router, "GET /old/?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(Some("/about/"), res.has_header("Location"));
+
+    // "admin:secret" in base64
+    let req_str =
+      "GET /about/us/ HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic YWRtaW46c2VjcmV0\r\n\r\n";
+    let res = handle(&router, req_str);
+    assert_eq!(
+      Some("text/html; charset=utf-8"),
+      res.has_header("Content-Type")
+    );
+
+    // A rule covers whole segments only
+    site.auth[0].prefix = "/about/u".to_string();
+    let router = Router::with_routes(&site, Routes::new(), cache());
+    let res = handle(
+      &router,
+      "GET /about/us/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(None, res.has_header("WWW-Authenticate"));
+  }
+
+  #[test]
+  fn files_are_read_when_requested() {
+    let root = TempRoot::new("files", &[("index.html", "old"), ("gone.html", "gone")]);
+    let router = Router::with_routes(&SiteConfig::new(&root.0), Routes::new(), cache());
+    let get = |path: &str| {
+      handle(
+        &router,
+        &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path),
+      )
+    };
+    assert_eq!("old", body(get("/")));
+    assert_eq!("gone", body(get("/gone.html")));
+
+    root.write("index.html", "newer");
+    root.write("added.html", "added");
+    root.remove("gone.html");
+
+    assert_eq!("newer", body(get("/")));
+    assert_eq!("added", body(get("/added.html")));
+    assert_eq!(http::StatusCode::NotFound, get("/gone.html").status_code());
+  }
 
   #[test]
-  fn turn_path_into_uri_valid() {
-    let path: PathBuf = PathBuf::from(r"./html/about/us/index.html");
-    let dir_depth = 2;
-    let path_str =
-      utils::turn_path_into_uri(&path, dir_depth, false).expect("Unable to turn filepath into uri");
+  fn directories_are_listed_where_configured() {
+    let root = TempRoot::new(
+      "listing",
+      &[
+        ("files/a.txt", "a"),
+        ("files/.secret", "s"),
+        ("files/docs/index.html", "docs"),
+        ("private/", ""),
+      ],
+    );
+    let mut site = SiteConfig::new(&root.0);
+    site.autoindex = vec!["/files/".to_string()];
+    let router = Router::with_routes(&site, Routes::new(), cache());
+    let send = |req: &str| {
+      handle(
+        &router,
+        &format!("{} HTTP/1.1\r\nHost: localhost\r\n\r\n", req),
+      )
+    };
+
+    let res = send("GET /files/");
+    assert_eq!(http::StatusCode::OK, res.status_code());
+    assert!(
+      res.get_body().contains("<a href=\"a.txt\">a.txt</a>"),
+      "{}",
+      res.get_body()
+    );
+    assert!(
+      res.get_body().contains("<a href=\"docs/\">docs/</a>"),
+      "{}",
+      res.get_body()
+    );
+    assert!(!res.get_body().contains(".secret"), "{}", res.get_body());
+    assert_eq!("docs", body(send("GET /files/docs/")));
+    assert_eq!(
+      http::StatusCode::MethodNotAllowed,
+      send("POST /files/").status_code()
+    );
+    assert_eq!(
+      http::StatusCode::NotFound,
+      send("GET /private/").status_code()
+    );
+    assert_eq!(
+      http::StatusCode::NotFound,
+      send("GET /files/missing/").status_code()
+    );
+
+    let res = send("GET /files?sort=size");
+    assert_eq!(http::StatusCode::MovedPermanently, res.status_code());
+    assert_eq!(Some("/files/?sort=size"), res.has_header("Location"));
+    let res = send("GET /files/a.txt/");
+    assert_eq!(Some("/files/a.txt"), res.has_header("Location"));
+  }
+
+  #[test]
+  fn handlers_next_to_static_files() {
+    let mut routes = Routes::new();
+    let hello = |req: &http::Request| {
+      let name = req.param("name").unwrap_or("world");
+      http::Response::new(http::StatusCode::OK, format!("Hello {}", name))
+    };
+    let echo =
+      |req: &http::Request| http::Response::new(http::StatusCode::OK, req.get_body().to_string());
+    add_route(
+      &mut routes,
+      http::RequestMethod::GET,
+      "/api/hello/:name/?",
+      Arc::new(hello),
+    )
+    .expect("Unable to add route");
+    add_route(&mut routes, http::RequestMethod::POST, "/", Arc::new(echo))
+      .expect("Unable to add route");
+    assert!(add_route(&mut routes, http::RequestMethod::POST, "/", Arc::new(echo)).is_err());
+    assert_eq!(
+      vec![
+        "POST /",
+        "GET, HEAD /api/hello/:name",
+        "GET, HEAD /api/hello/:name/"
+      ],
+      describe_routes(&routes)
+    );
+    let router = Router::with_routes(&SiteConfig::new("./html/"), routes, cache());
+
+    let res = handle(
+      &router,
+      "GET /api/hello/you?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!("Hello you", res.get_body());
+
+    let res = handle(&router, "POST / HTTP/1.1\r\nHost: localhost\r\n\r\nposted");
+    assert_eq!("posted", res.get_body());
+
+    let res = handle(&router, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(
+      Some("text/html; charset=utf-8"),
+      res.has_header("Content-Type")
+    );
+  }
+
+  #[test]
+  fn method_not_allowed_and_options() {
+    let mut routes = Routes::new();
+    let ok = |_: &http::Request| http::Response::new(http::StatusCode::OK, "");
+    add_route(
+      &mut routes,
+      http::RequestMethod::DELETE,
+      "/about/",
+      Arc::new(ok),
+    )
+    .expect("Unable to add route");
+    add_route(
+      &mut routes,
+      http::RequestMethod::POST,
+      "/api/items",
+      Arc::new(ok),
+    )
+    .expect("Unable to add route");
+    let router = Router::with_routes(&SiteConfig::new("./html/"), routes, cache());
+
+    let res = handle(&router, "POST / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(http::StatusCode::MethodNotAllowed, res.status_code());
+    assert_eq!(Some("GET, HEAD, OPTIONS"), res.has_header("Allow"));
+
+    let res = handle(
+      &router,
+      "OPTIONS /about/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(http::StatusCode::NoContent, res.status_code());
+    assert_eq!(Some("GET, HEAD, DELETE, OPTIONS"), res.has_header("Allow"));
+
+    let res = handle(&router, "OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(
+      Some("GET, HEAD, POST, DELETE, OPTIONS"),
+      res.has_header("Allow")
+    );
+
+    let res = handle(
+      &router,
+      "GET /api/items HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(Some("POST, OPTIONS"), res.has_header("Allow"));
+  }
+
+  #[test]
+  fn head_is_answered_by_get() {
+    let mut routes = Routes::new();
+    let hello = |_: &http::Request| http::Response::new(http::StatusCode::OK, "Hello");
+    add_route(
+      &mut routes,
+      http::RequestMethod::GET,
+      "/api/hello",
+      Arc::new(hello),
+    )
+    .expect("Unable to add route");
+    let router = Router::with_routes(&SiteConfig::new("./html/"), routes, cache());
+
+    let res = handle(
+      &router,
+      "HEAD /api/hello HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(http::StatusCode::OK, res.status_code());
+    assert_eq!(
+      "Hello",
+      res.get_body(),
+      "The body is removed when the response is sent"
+    );
+
+    let res = handle(
+      &router,
+      "OPTIONS /api/hello HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert_eq!(Some("GET, HEAD, OPTIONS"), res.has_header("Allow"));
+  }
+
+  #[test]
+  fn trailing_slash_redirects() {
+    let mut site = SiteConfig::new("./html/");
+    site.slash_redirect = http::StatusCode::PermanentRedirect;
+    let router = Router::with_routes(&site, Routes::new(), cache());
+    let get = |path: &str| {
+      handle(
+        &router,
+        &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path),
+      )
+    };
+
+    let res = get("/about/us");
+    assert_eq!(http::StatusCode::PermanentRedirect, res.status_code());
+    assert_eq!(Some("/about/us/"), res.has_header("Location"));
+    assert_eq!(
+      Some("/about/index.html"),
+      get("/about/index.html/").has_header("Location")
+    );
+    assert_eq!(http::StatusCode::OK, get("/about/us/").status_code());
+    assert_eq!(http::StatusCode::NotFound, get("/about/them").status_code());
+  }
+
+  #[test]
+  fn errors_are_answered_with_error_pages() {
+    let root = TempRoot::new(
+      "errors",
+      &[
+        ("index.html", "home"),
+        ("404.html", "<h1>Nothing here</h1>"),
+        ("errors/gone.html", "<h1>Wrong method</h1>"),
+      ],
+    );
+    let mut site = SiteConfig::new(&root.0);
+    site
+      .error_pages
+      .insert(405, PathBuf::from("errors/gone.html"));
+    let router = Router::with_routes(&site, Routes::new(), cache());
+    let send = |req: &str, accept: &str| {
+      handle(
+        &router,
+        &format!("{} HTTP/1.1\r\nHost: localhost\r\n{}\r\n", req, accept),
+      )
+    };
+
+    let res = send("GET /missing", "");
+    assert_eq!(http::StatusCode::NotFound, res.status_code());
+    assert_eq!("<h1>Nothing here</h1>", res.get_body());
+    assert_eq!(
+      Some("text/html; charset=utf-8"),
+      res.has_header("Content-Type")
+    );
+    assert_eq!("<h1>Wrong method</h1>", send("DELETE /", "").get_body());
+
+    let res = send("GET /missing", "Accept: application/json\r\n");
+    assert_eq!("{\"status\":404,\"error\":\"Not Found\"}\n", res.get_body());
+    assert_eq!(Some("application/json"), res.has_header("Content-Type"));
 
-    assert_eq!(path_str, "/about/us/");
+    root.remove("404.html");
+    let res = send("GET /missing", "");
+    assert_eq!("Not Found\n", res.get_body());
+    assert_eq!(
+      Some("text/plain; charset=utf-8"),
+      res.has_header("Content-Type")
+    );
   }
 }
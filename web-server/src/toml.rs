@@ -0,0 +1,504 @@
+//! A small parser for the subset of TOML which is used
+//! by the configuration file. Supported are tables, arrays
+//! of tables, strings, integers, booleans, arrays and
+//! inline tables. Every value remembers the line it was
+//! defined on, so that later validation can point at it.
+
+use std::{iter::Peekable, str::Chars};
+
+use super::errors::ConfigError;
+
+/// A parsed value and the line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+  pub line: usize,
+  pub kind: Kind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+  String(String),
+  Integer(i64),
+  Boolean(bool),
+  Array(Vec<Value>),
+  Table(Table),
+}
+
+impl Kind {
+  /// A name for the type of the value, used in error messages.
+  pub fn type_name(&self) -> &'static str {
+    match *self {
+      Kind::String(_) => "string",
+      Kind::Integer(_) => "integer",
+      Kind::Boolean(_) => "boolean",
+      Kind::Array(_) => "array",
+      Kind::Table(_) => "table",
+    }
+  }
+}
+
+/// A table keeps its entries in the order they were defined.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+  entries: Vec<(String, Value)>,
+}
+
+impl Table {
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    self
+      .entries
+      .iter()
+      .find(|&&(ref k, _)| k == key)
+      .map(|&(_, ref v)| v)
+  }
+
+  pub fn entries(&self) -> &[(String, Value)] {
+    &self.entries
+  }
+
+  fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+    self
+      .entries
+      .iter_mut()
+      .find(|&&mut (ref k, _)| k == key)
+      .map(|&mut (_, ref mut v)| v)
+  }
+
+  fn insert(&mut self, key: String, value: Value) -> Result<(), ConfigError> {
+    if self.get(&key).is_some() {
+      return Err(ConfigError::syntax(
+        value.line,
+        format!("duplicate key `{}`", key),
+      ));
+    }
+    self.entries.push((key, value));
+    Ok(())
+  }
+}
+
+/// Parses a whole document into its root table.
+pub fn parse(input: &str) -> Result<Table, ConfigError> {
+  Parser {
+    chars: input.chars().peekable(),
+    line: 1,
+  }
+  .parse_document()
+}
+
+struct Parser<'a> {
+  chars: Peekable<Chars<'a>>,
+  line: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn parse_document(&mut self) -> Result<Table, ConfigError> {
+    let mut root = Table::default();
+    let mut current: Vec<String> = Vec::new();
+    let mut defined: Vec<Vec<String>> = Vec::new();
+
+    loop {
+      self.skip_whitespace_and_comments(true);
+      match self.chars.peek().cloned() {
+        None => break,
+        Some('[') => {
+          let line = self.line;
+          self.chars.next();
+          let is_array = self.eat('[');
+          let path = self.parse_header_path()?;
+          self.expect(']')?;
+          if is_array {
+            self.expect(']')?;
+          }
+          self.expect_line_end()?;
+
+          if is_array {
+            push_array_table(&mut root, &path, line)?;
+          } else {
+            if defined.contains(&path) {
+              let message = format!("table [{}] is defined twice", path.join("."));
+              return Err(ConfigError::syntax(line, message));
+            }
+            defined.push(path.clone());
+            table_at(&mut root, &path, line)?;
+          }
+          current = path;
+        }
+        Some(_) => {
+          let key = self.parse_key()?;
+          self.skip_whitespace_and_comments(false);
+          self.expect('=')?;
+          self.skip_whitespace_and_comments(false);
+          let value = self.parse_value()?;
+          self.expect_line_end()?;
+
+          let line = value.line;
+          table_at(&mut root, &current, line)?.insert(key, value)?;
+        }
+      }
+    }
+
+    Ok(root)
+  }
+
+  fn parse_header_path(&mut self) -> Result<Vec<String>, ConfigError> {
+    let mut path = Vec::new();
+    loop {
+      self.skip_whitespace_and_comments(false);
+      path.push(self.parse_key()?);
+      self.skip_whitespace_and_comments(false);
+      if !self.eat('.') {
+        return Ok(path);
+      }
+    }
+  }
+
+  fn parse_key(&mut self) -> Result<String, ConfigError> {
+    match self.chars.peek().cloned() {
+      Some('"') => self.parse_basic_string(),
+      Some('\'') => self.parse_literal_string(),
+      Some(c) if is_bare_key_char(c) => {
+        let mut key = String::new();
+        while let Some(c) = self.chars.peek().cloned() {
+          if !is_bare_key_char(c) {
+            break;
+          }
+          key.push(c);
+          self.chars.next();
+        }
+        Ok(key)
+      }
+      Some(c) => Err(self.error(format!("expected a key, found `{}`", c))),
+      None => Err(self.error("expected a key, found end of file")),
+    }
+  }
+
+  fn parse_value(&mut self) -> Result<Value, ConfigError> {
+    let line = self.line;
+    let kind = match self.chars.peek().cloned() {
+      Some('"') => Kind::String(self.parse_basic_string()?),
+      Some('\'') => Kind::String(self.parse_literal_string()?),
+      Some('[') => Kind::Array(self.parse_array()?),
+      Some('{') => Kind::Table(self.parse_inline_table()?),
+      Some(c) if c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '_' => {
+        let mut word = String::new();
+        while let Some(c) = self.chars.peek().cloned() {
+          if !(c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '_' || c == '.') {
+            break;
+          }
+          word.push(c);
+          self.chars.next();
+        }
+        match word.as_str() {
+          "true" => Kind::Boolean(true),
+          "false" => Kind::Boolean(false),
+          _ => word
+            .replace('_', "")
+            .parse::<i64>()
+            .map(Kind::Integer)
+            .map_err(|_| self.error(format!("invalid value `{}`", word)))?,
+        }
+      }
+      Some('\n') => return Err(self.error("expected a value, found end of line")),
+      Some(c) => return Err(self.error(format!("expected a value, found `{}`", c))),
+      None => return Err(self.error("expected a value, found end of file")),
+    };
+    Ok(Value { line, kind })
+  }
+
+  fn parse_array(&mut self) -> Result<Vec<Value>, ConfigError> {
+    self.expect('[')?;
+    let mut values = Vec::new();
+    loop {
+      self.skip_whitespace_and_comments(true);
+      if self.eat(']') {
+        return Ok(values);
+      }
+      values.push(self.parse_value()?);
+      self.skip_whitespace_and_comments(true);
+      if !self.eat(',') {
+        self.skip_whitespace_and_comments(true);
+        self.expect(']')?;
+        return Ok(values);
+      }
+    }
+  }
+
+  fn parse_inline_table(&mut self) -> Result<Table, ConfigError> {
+    self.expect('{')?;
+    let mut table = Table::default();
+    self.skip_whitespace_and_comments(false);
+    if self.eat('}') {
+      return Ok(table);
+    }
+    loop {
+      self.skip_whitespace_and_comments(false);
+      let key = self.parse_key()?;
+      self.skip_whitespace_and_comments(false);
+      self.expect('=')?;
+      self.skip_whitespace_and_comments(false);
+      let value = self.parse_value()?;
+      table.insert(key, value)?;
+      self.skip_whitespace_and_comments(false);
+      if !self.eat(',') {
+        self.expect('}')?;
+        return Ok(table);
+      }
+    }
+  }
+
+  fn parse_basic_string(&mut self) -> Result<String, ConfigError> {
+    self.expect('"')?;
+    let mut s = String::new();
+    loop {
+      match self.chars.next() {
+        Some('"') => return Ok(s),
+        Some('\\') => {
+          let escaped = match self.chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some(c) => return Err(self.error(format!("invalid escape sequence `\\{}`", c))),
+            None => return Err(self.error("unterminated string")),
+          };
+          s.push(escaped);
+        }
+        Some('\n') | None => return Err(self.error("unterminated string")),
+        Some(c) => s.push(c),
+      }
+    }
+  }
+
+  fn parse_literal_string(&mut self) -> Result<String, ConfigError> {
+    self.expect('\'')?;
+    let mut s = String::new();
+    loop {
+      match self.chars.next() {
+        Some('\'') => return Ok(s),
+        Some('\n') | None => return Err(self.error("unterminated string")),
+        Some(c) => s.push(c),
+      }
+    }
+  }
+
+  /// Skips spaces and comments, and newlines if `newlines` is set.
+  fn skip_whitespace_and_comments(&mut self, newlines: bool) {
+    while let Some(c) = self.chars.peek().cloned() {
+      match c {
+        ' ' | '\t' | '\r' => {}
+        '\n' if newlines => self.line += 1,
+        '#' => {
+          while let Some(&c) = self.chars.peek() {
+            if c == '\n' {
+              break;
+            }
+            self.chars.next();
+          }
+        }
+        _ => return,
+      }
+      if c != '#' {
+        self.chars.next();
+      }
+    }
+  }
+
+  fn expect_line_end(&mut self) -> Result<(), ConfigError> {
+    self.skip_whitespace_and_comments(false);
+    match self.chars.next() {
+      None => Ok(()),
+      Some('\n') => {
+        self.line += 1;
+        Ok(())
+      }
+      Some(c) => Err(self.error(format!("expected end of line, found `{}`", c))),
+    }
+  }
+
+  fn expect(&mut self, expected: char) -> Result<(), ConfigError> {
+    match self.chars.next() {
+      Some(c) if c == expected => Ok(()),
+      Some('\n') => Err(self.error(format!("expected `{}`, found end of line", expected))),
+      Some(c) => Err(self.error(format!("expected `{}`, found `{}`", expected, c))),
+      None => Err(self.error(format!("expected `{}`, found end of file", expected))),
+    }
+  }
+
+  fn eat(&mut self, expected: char) -> bool {
+    if self.chars.peek() == Some(&expected) {
+      self.chars.next();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn error<S: Into<String>>(&self, message: S) -> ConfigError {
+    ConfigError::syntax(self.line, message)
+  }
+}
+
+fn is_bare_key_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Finds the table at the given path, creating tables which
+/// do not exist yet. When a part of the path is an array of
+/// tables, the last table in the array is used.
+fn table_at<'t>(
+  root: &'t mut Table,
+  path: &[String],
+  line: usize,
+) -> Result<&'t mut Table, ConfigError> {
+  let mut table = root;
+  for key in path {
+    if table.get(key).is_none() {
+      table.insert(
+        key.clone(),
+        Value {
+          line,
+          kind: Kind::Table(Table::default()),
+        },
+      )?;
+    }
+    let value = table.get_mut(key).expect("Table entry was just inserted");
+    table = match value.kind {
+      Kind::Table(ref mut table) => table,
+      Kind::Array(ref mut values) => match values.last_mut().map(|v| &mut v.kind) {
+        Some(&mut Kind::Table(ref mut table)) => table,
+        _ => {
+          return Err(ConfigError::syntax(
+            line,
+            format!("`{}` is not a table", key),
+          ))
+        }
+      },
+      _ => {
+        return Err(ConfigError::syntax(
+          line,
+          format!("`{}` is not a table", key),
+        ))
+      }
+    };
+  }
+  Ok(table)
+}
+
+/// Appends a new table to the array of tables at the given path.
+fn push_array_table(root: &mut Table, path: &[String], line: usize) -> Result<(), ConfigError> {
+  let (key, parent_path) = path.split_last().expect("Header path is never empty");
+  let parent = table_at(root, parent_path, line)?;
+
+  let new_table = Value {
+    line,
+    kind: Kind::Table(Table::default()),
+  };
+  if parent.get(key).is_none() {
+    return parent.insert(
+      key.clone(),
+      Value {
+        line,
+        kind: Kind::Array(vec![new_table]),
+      },
+    );
+  }
+  match parent.get_mut(key).map(|v| &mut v.kind) {
+    Some(&mut Kind::Array(ref mut values)) => {
+      values.push(new_table);
+      Ok(())
+    }
+    _ => Err(ConfigError::syntax(
+      line,
+      format!("`{}` is not an array of tables", key),
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn string(line: usize, s: &str) -> Value {
+    Value {
+      line,
+      kind: Kind::String(s.to_string()),
+    }
+  }
+
+  #[test]
+  fn parse_key_values() {
+    let doc = concat!(
+      "# A comment\nname = \"web\" # trailing\nport = 8_080\ndev = true\n",
+      "index = [\n  'index.html',\n  \"default.html\",\n]\n"
+    );
+    let table = match parse(doc) {
+      Ok(table) => table,
+      Err(e) => panic!("Should not get error on valid document: {}", e),
+    };
+
+    assert_eq!(Some(&string(2, "web")), table.get("name"));
+    assert_eq!(
+      Some(&Kind::Integer(8080)),
+      table.get("port").map(|v| &v.kind)
+    );
+    assert_eq!(
+      Some(&Kind::Boolean(true)),
+      table.get("dev").map(|v| &v.kind)
+    );
+    assert_eq!(
+      Some(&Kind::Array(vec![
+        string(6, "index.html"),
+        string(7, "default.html")
+      ])),
+      table.get("index").map(|v| &v.kind)
+    );
+  }
+
+  #[test]
+  fn parse_tables_and_arrays_of_tables() {
+    let doc = concat!(
+      "[server]\nthreads = 2\n\n[[site]]\nroot = \"a\"\n",
+      "[site.headers]\n\"X-Test\" = \"1\"\n\n[[site]]\nroot = \"b\"\n"
+    );
+    let table = match parse(doc) {
+      Ok(table) => table,
+      Err(e) => panic!("Should not get error on valid document: {}", e),
+    };
+
+    let sites = match table.get("site").map(|v| &v.kind) {
+      Some(&Kind::Array(ref sites)) => sites,
+      other => panic!("Expected an array of sites, got: {:?}", other),
+    };
+    assert_eq!(2, sites.len());
+    match sites[0].kind {
+      Kind::Table(ref site) => {
+        assert_eq!(Some(&string(5, "a")), site.get("root"));
+        assert!(
+          site.get("headers").is_some(),
+          "Nested table was not added to last site"
+        );
+      }
+      ref other => panic!("Expected a table, got: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn errors_report_line() {
+    let invalid = vec![
+      ("a = 1\nb = \n", 2),
+      ("a = 1\na = 2\n", 2),
+      ("[a]\n[a]\n", 2),
+      ("\n\nname = \"unterminated\n", 3),
+      ("a = 1 b = 2\n", 1),
+      ("a = 1.5\n", 1),
+    ];
+
+    for (doc, line) in invalid {
+      match parse(doc) {
+        Ok(_) => panic!("Should get error on invalid document: {:?}", doc),
+        Err(e) => assert_eq!(Some(line), e.line(), "Wrong line for error in: {:?}", doc),
+      }
+    }
+  }
+}
@@ -0,0 +1,190 @@
+use std::{fs::File, io::{self, Read, Write}, net::TcpStream};
+
+use http::Body;
+
+/// Sends a body after the head of its response has been written,
+/// and gives the number of bytes of the body which were sent. A
+/// chunked body is sent in chunks as they come, followed by the
+/// last, empty chunk.
+pub fn send_body(mut socket: &TcpStream, body: Body, chunked: bool) -> io::Result<u64> {
+  if chunked {
+    let sent = write_body(&mut Chunked(socket), body)?;
+    socket.write_all(b"0\r\n\r\n")?;
+    return Ok(sent);
+  }
+  match body {
+    Body::File(mut file, length) => send_file(socket, &mut file, length),
+    body => write_body(&mut socket, body),
+  }
+}
+
+/// Writes the body through a buffer.
+fn write_body(out: &mut Write, body: Body) -> io::Result<u64> {
+  match body {
+    Body::Bytes(bytes) => {
+      out.write_all(&bytes)?;
+      Ok(bytes.len() as u64)
+    }
+    Body::File(file, length) => copy_exact(file, length, out),
+    Body::Reader(reader, Some(length)) => copy_exact(reader, length, out),
+    Body::Reader(mut reader, None) => io::copy(&mut reader, out),
+    Body::Chunks(chunks) => write_chunks(chunks, out),
+    Body::Channel(receiver) => write_chunks(receiver.iter(), out),
+  }
+}
+
+fn write_chunks<I: Iterator<Item = Vec<u8>>>(chunks: I, out: &mut Write) -> io::Result<u64> {
+  let mut sent = 0;
+  for chunk in chunks {
+    out.write_all(&chunk)?;
+    sent += chunk.len() as u64;
+  }
+  Ok(sent)
+}
+
+/// Copies `length` bytes. A reader which turns out to be shorter
+/// is an error, since the length has been sent already.
+fn copy_exact<R: Read>(reader: R, length: u64, out: &mut Write) -> io::Result<u64> {
+  let copied = io::copy(&mut reader.take(length), out)?;
+  if copied < length {
+    return Err(io::Error::new(
+      io::ErrorKind::UnexpectedEof,
+      format!("body ended after {} of {} bytes", copied, length),
+    ));
+  }
+  Ok(copied)
+}
+
+/// Writes everything it is given as one chunk of
+/// the chunked transfer coding.
+struct Chunked<W: Write>(W);
+
+impl<W: Write> Write for Chunked<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    // An empty chunk would end the body
+    if buf.is_empty() {
+      return Ok(0);
+    }
+    write!(self.0, "{:x}\r\n", buf.len())?;
+    self.0.write_all(buf)?;
+    self.0.write_all(b"\r\n")?;
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.flush()
+  }
+}
+
+/// Sends the file from its current position. The kernel copies the
+/// file to the socket where it can, which only works since the
+/// connection is plaintext, and it is copied through a buffer
+/// otherwise.
+fn send_file(mut socket: &TcpStream, file: &mut File, length: u64) -> io::Result<u64> {
+  let sent = kernel_copy(socket, file, length)?;
+  Ok(sent + copy_exact(file, length - sent, &mut socket)?)
+}
+
+/// Copies as much of the file as the kernel can with `sendfile`, and
+/// gives the number of bytes which were copied. Copying stops without
+/// an error when `sendfile` cannot be used for the file, so that the
+/// rest is copied through a buffer.
+#[cfg(target_os = "linux")]
+fn kernel_copy(socket: &TcpStream, file: &File, length: u64) -> io::Result<u64> {
+  use std::os::unix::io::AsRawFd;
+
+  // `sendfile` copies at most this many bytes in one call
+  const MAX_CHUNK: u64 = 0x7fff_f000;
+  let mut sent = 0;
+  while sent < length {
+    let chunk = (length - sent).min(MAX_CHUNK) as usize;
+    let result = unsafe {
+      ::libc::sendfile(
+        socket.as_raw_fd(),
+        file.as_raw_fd(),
+        ::std::ptr::null_mut(),
+        chunk,
+      )
+    };
+    match result {
+      0 => break,
+      n if n > 0 => sent += n as u64,
+      _ => {
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+          Some(::libc::EINTR) => continue,
+          Some(::libc::EINVAL) | Some(::libc::ENOSYS) if sent == 0 => break,
+          // The write timeout of the socket makes `sendfile` give up with `EAGAIN`
+          Some(::libc::EAGAIN) => return Err(io::Error::new(io::ErrorKind::TimedOut, error)),
+          _ => return Err(error),
+        }
+      }
+    }
+  }
+  Ok(sent)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel_copy(_socket: &TcpStream, _file: &File, _length: u64) -> io::Result<u64> {
+  Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{fs, net::TcpListener, thread};
+
+  /// Sends the body to a client, and gives the number of bytes
+  /// which were sent and what the client received.
+  fn send(body: Body, chunked: bool) -> (u64, Vec<u8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to bind listener");
+    let address = listener.local_addr().expect("Unable to get address");
+    let client = thread::spawn(move || {
+      let mut received = Vec::new();
+      let mut stream = TcpStream::connect(address).expect("Unable to connect");
+      stream.read_to_end(&mut received).expect("Unable to read");
+      received
+    });
+
+    let (stream, _) = listener.accept().expect("Unable to accept connection");
+    let sent = send_body(&stream, body, chunked).expect("Unable to send body");
+    drop(stream);
+    (sent, client.join().expect("Client failed"))
+  }
+
+  #[test]
+  fn file_is_sent_in_full() {
+    let body = Body::file(File::open("Cargo.toml").expect("Unable to open file"))
+      .expect("Unable to open body");
+    let length = body.length();
+    let (sent, received) = send(body, false);
+    assert_eq!(length, Some(sent));
+    assert_eq!(
+      fs::read("Cargo.toml").expect("Unable to read file"),
+      received
+    );
+  }
+
+  #[test]
+  fn bodies_of_unknown_length_are_chunked() {
+    let (sender, body) = Body::channel();
+    let producer = thread::spawn(move || {
+      for chunk in &["hello", "", " world"] {
+        sender
+          .send(chunk.as_bytes().to_vec())
+          .expect("Unable to send chunk");
+      }
+    });
+    let (sent, received) = send(body, true);
+    producer.join().expect("Producer failed");
+    assert_eq!(11, sent);
+    assert_eq!(
+      &b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"[..],
+      &received[..]
+    );
+
+    let (sent, received) = send(Body::reader(&b"until the end"[..]), false);
+    assert_eq!(13, sent);
+    assert_eq!(&b"until the end"[..], &received[..]);
+  }
+}
@@ -1,70 +1,510 @@
-use std::{fs::{self, DirEntry}, io::Read, net::TcpStream, path::{Path, PathBuf}};
-
-use super::errors::ReadStreamError;
-
-const MAX_REQUEST_SIZE: usize = 1024;
+use std::{collections::HashMap, fs::{self, DirEntry}, io, path::Path};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Visits all files in from given dir to deepest nested
-/// subdir. Applies the function to all files.
-pub fn visit_dir<F>(dir_path: &Path, f: &mut F)
+/// subdir. Applies the function to all files and stops
+/// at the first error. Links to directories are visited as
+/// files, so a link to a parent does not visit it forever.
+pub fn visit_dir<F>(dir_path: &Path, f: &mut F) -> io::Result<()>
 where
-  F: FnMut(DirEntry, usize),
+  F: FnMut(DirEntry, usize) -> io::Result<()>,
 {
   _visit_dir(dir_path, f, 0)
 }
 
 /// Private function to keep track of current depth of recursion
-fn _visit_dir<F>(dir_path: &Path, f: &mut F, dir_depth: usize)
+fn _visit_dir<F>(dir_path: &Path, f: &mut F, dir_depth: usize) -> io::Result<()>
 where
-  F: FnMut(DirEntry, usize),
+  F: FnMut(DirEntry, usize) -> io::Result<()>,
 {
-  let dir_entries = fs::read_dir(dir_path).expect(&format!(
-    "Wasn't able to read directory at path: {:?}",
-    dir_path
-  ));
-
-  let (dirs, files): (Vec<DirEntry>, Vec<DirEntry>) = dir_entries
-    .map(|dir_entry| dir_entry.unwrap())
-    .partition(|dir_entry| fs::metadata(dir_entry.path()).unwrap().is_dir());
-
-  dirs
-    .into_iter()
-    .map(|dir_entry| dir_entry.path())
-    .for_each(|path_buf| _visit_dir(&path_buf, f, dir_depth + 1));
-
-  files
-    .into_iter()
-    .for_each(|dir_entry| f(dir_entry, dir_depth));
+  let mut dirs = Vec::new();
+  let mut files = Vec::new();
+  for dir_entry in fs::read_dir(dir_path)? {
+    let dir_entry = dir_entry?;
+    if dir_entry.file_type()?.is_dir() {
+      dirs.push(dir_entry.path());
+    } else {
+      files.push(dir_entry);
+    }
+  }
+
+  for path_buf in dirs {
+    _visit_dir(&path_buf, f, dir_depth + 1)?;
+  }
+  for dir_entry in files {
+    f(dir_entry, dir_depth)?;
+  }
+  Ok(())
 }
 
-/// Turns a possible global file path into an uri path
-pub fn turn_path_into_uri(path: &Path, dir_depth: usize, inc_filename: bool) -> Option<String> {
-  let (skip_amount, take_amount) = if inc_filename {
-    (0, dir_depth + 1)
-  } else {
-    (1, dir_depth)
+/// Guesses the mime type of a file from its extension. The
+/// overrides are checked first, and unknown extensions are
+/// served as `application/octet-stream`.
+pub fn mime_type(path: &Path, overrides: &HashMap<String, String>) -> String {
+  let ext = path
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .map(|ext| ext.to_lowercase())
+    .unwrap_or_default();
+
+  if let Some(mime) = overrides.get(&ext) {
+    return mime.clone();
+  }
+
+  let mime = match ext.as_str() {
+    "html" | "htm" => "text/html; charset=utf-8",
+    "css" => "text/css; charset=utf-8",
+    "js" => "application/javascript; charset=utf-8",
+    "json" => "application/json",
+    "txt" => "text/plain; charset=utf-8",
+    "xml" => "application/xml",
+    "svg" => "image/svg+xml",
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "ico" => "image/x-icon",
+    "woff" => "font/woff",
+    "woff2" => "font/woff2",
+    "pdf" => "application/pdf",
+    _ => "application/octet-stream",
   };
+  mime.to_string()
+}
+
+/// Decodes `%XX` escapes, as used in the path of a uri. Gives
+/// `None` when a `%` is not followed by two hex digits.
+pub fn percent_decode(input: &str) -> Option<Vec<u8>> {
+  let input = input.as_bytes();
+  let mut output = Vec::with_capacity(input.len());
+  let mut i = 0;
+  while i < input.len() {
+    if input[i] == b'%' {
+      let hex = input.get(i + 1..i + 3)?;
+      if !hex.iter().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+      }
+      let hex = ::std::str::from_utf8(hex).ok()?;
+      output.push(u8::from_str_radix(hex, 16).ok()?);
+      i += 3;
+    } else {
+      output.push(input[i]);
+      i += 1;
+    }
+  }
+  Some(output)
+}
+
+/// Encodes every byte except letters, digits and `-._~` as
+/// `%XX`, so that the string can be a segment of a uri path.
+pub fn percent_encode(input: &str) -> String {
+  let mut output = String::with_capacity(input.len());
+  for &b in input.as_bytes() {
+    match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => output.push(b as char),
+      _ => output.push_str(&format!("%{:02X}", b)),
+    }
+  }
+  output
+}
+
+/// Decodes the `%XX` escapes of characters which may be written as
+/// they are in a path, and writes the hex digits of the others in
+/// upper case, so that equivalent paths are written the same way
+/// (RFC 3986, 6.2.2). Escapes such as `%2F` and `%3F` are kept, as
+/// decoding them would change how the path is read.
+pub fn normalize_path(path: &str) -> String {
+  let input = path.as_bytes();
+  let mut output = String::with_capacity(path.len());
+  let mut i = 0;
+  while i < input.len() {
+    let hex = input
+      .get(i + 1..i + 3)
+      .and_then(|hex| ::std::str::from_utf8(hex).ok());
+    let byte = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match (input[i], hex, byte) {
+      (b'%', Some(hex), Some(byte)) if hex.bytes().all(|c| c.is_ascii_hexdigit()) => {
+        // Letters, digits, `-._~`, the sub-delimiters, `:` and `@`
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&byte) {
+          output.push(byte as char);
+        } else {
+          output.push_str(&format!("%{:02X}", byte));
+        }
+        i += 3;
+      }
+      _ => {
+        // Only ASCII is escaped, so a character is never split
+        let len = path[i..].chars().next().map_or(1, |c| c.len_utf8());
+        output.push_str(&path[i..i + len]);
+        i += len;
+      }
+    }
+  }
+  output
+}
+
+/// Escapes the characters which have a meaning in HTML.
+pub fn html_escape(input: &str) -> String {
+  let mut output = String::with_capacity(input.len());
+  for c in input.chars() {
+    match c {
+      '&' => output.push_str("&amp;"),
+      '<' => output.push_str("&lt;"),
+      '>' => output.push_str("&gt;"),
+      '"' => output.push_str("&quot;"),
+      '\'' => output.push_str("&#39;"),
+      c => output.push(c),
+    }
+  }
+  output
+}
+
+/// Decodes standard base64 with padding, as used by the
+/// `Authorization` header. Gives `None` on invalid input.
+pub fn decode_base64(input: &str) -> Option<Vec<u8>> {
+  fn value(c: u8) -> Option<u32> {
+    match c {
+      b'A'..=b'Z' => Some((c - b'A') as u32),
+      b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+      b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+      b'+' => Some(62),
+      b'/' => Some(63),
+      _ => None,
+    }
+  }
+
+  let input = input.as_bytes();
+  if input.len() % 4 != 0 {
+    return None;
+  }
+
+  let mut output = Vec::with_capacity(input.len() / 4 * 3);
+  let last_chunk = input.len() / 4;
+  for (i, chunk) in input.chunks(4).enumerate() {
+    let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+    if (padding > 0 && i + 1 != last_chunk) || padding > 2 {
+      return None;
+    }
+
+    let mut bits: u32 = 0;
+    for &c in &chunk[..4 - padding] {
+      bits = (bits << 6) | value(c)?;
+    }
+    bits <<= 6 * padding as u32;
+
+    output.push((bits >> 16) as u8);
+    if padding < 2 {
+      output.push((bits >> 8) as u8);
+    }
+    if padding < 1 {
+      output.push(bits as u8);
+    }
+  }
+  Some(output)
+}
+
+const MONTHS: [&str; 12] = [
+  "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A point in time split into its calendar date and time of day in UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateTime {
+  pub year: i64,
+  pub month: u32,
+  pub day: u32,
+  pub hour: u32,
+  pub minute: u32,
+  pub second: u32,
+}
 
-  path
-    .iter()
-    .rev()
-    .skip(skip_amount)
-    .take(take_amount)
-    .collect::<Vec<_>>()
-    .iter()
-    .rev()
-    .collect::<PathBuf>()
-    .to_str()
-    .map(|s| if inc_filename || s.len() == 0 { format!("/{}", s) } else { format!("/{}/", s) })
+impl DateTime {
+  pub fn from_system_time(time: SystemTime) -> DateTime {
+    let secs = time
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_secs() as i64)
+      .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Converts days since the epoch to a civil date, from
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+      (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    DateTime {
+      year,
+      month,
+      day,
+      hour: (secs_of_day / 3600) as u32,
+      minute: (secs_of_day % 3600 / 60) as u32,
+      second: (secs_of_day % 60) as u32,
+    }
+  }
+
+  /// Formats the time as in the Common Log Format, such
+  /// as `10/Oct/2000:13:55:36 +0000`.
+  pub fn to_clf(&self) -> String {
+    format!(
+      "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+      self.day,
+      MONTHS[self.month as usize - 1],
+      self.year,
+      self.hour,
+      self.minute,
+      self.second
+    )
+  }
+
+  /// Formats the time as in RFC 3339, such as `2000-10-10T13:55:36Z`.
+  pub fn to_rfc3339(&self) -> String {
+    format!(
+      "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+      self.year, self.month, self.day, self.hour, self.minute, self.second
+    )
+  }
+}
+
+/// Whether an `Accept` header prefers JSON to HTML, going by the
+/// quality of `application/json` and of `text/html`. Wildcards
+/// count for neither, so clients which accept anything get HTML.
+pub fn prefers_json(accept: &str) -> bool {
+  let mut json = 0.0;
+  let mut html = 0.0;
+  for range in accept.split(',') {
+    let mut params = range.split(';');
+    let media_type = params.next().unwrap_or("").trim().to_lowercase();
+    let quality = params
+      .filter_map(|param| {
+        let mut parts = param.splitn(2, '=');
+        match (parts.next().map(|name| name.trim()), parts.next()) {
+          (Some("q"), Some(value)) => value.trim().parse::<f32>().ok(),
+          _ => None,
+        }
+      })
+      .next()
+      .unwrap_or(1.0);
+    match media_type.as_str() {
+      "application/json" => json = quality,
+      "text/html" => html = quality,
+      _ => {}
+    }
+  }
+  json > 0.0 && json > html
 }
 
-/// Reads request from stream and returns a string which contains the request in UTF8
-pub fn read_string_from_stream(stream: &mut TcpStream) -> Result<String, ReadStreamError> {
-  let mut buffer: Box<[u8]> = Box::new([0; MAX_REQUEST_SIZE]);
-  let bytes_read = stream.read(&mut buffer)?;
-  let mut buffer_vec = buffer.into_vec();
-  buffer_vec.truncate(bytes_read);
+/// Compares two byte strings in time which only depends on their
+/// lengths, so that the time does not tell how much of a secret
+/// was guessed right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+const SHA256_K: [u32; 64] = [
+  0x428a_2f98, 0x7137_4491, 0xb5c0_fbcf, 0xe9b5_dba5,
+  0x3956_c25b, 0x59f1_11f1, 0x923f_82a4, 0xab1c_5ed5,
+  0xd807_aa98, 0x1283_5b01, 0x2431_85be, 0x550c_7dc3,
+  0x72be_5d74, 0x80de_b1fe, 0x9bdc_06a7, 0xc19b_f174,
+  0xe49b_69c1, 0xefbe_4786, 0x0fc1_9dc6, 0x240c_a1cc,
+  0x2de9_2c6f, 0x4a74_84aa, 0x5cb0_a9dc, 0x76f9_88da,
+  0x983e_5152, 0xa831_c66d, 0xb003_27c8, 0xbf59_7fc7,
+  0xc6e0_0bf3, 0xd5a7_9147, 0x06ca_6351, 0x1429_2967,
+  0x27b7_0a85, 0x2e1b_2138, 0x4d2c_6dfc, 0x5338_0d13,
+  0x650a_7354, 0x766a_0abb, 0x81c2_c92e, 0x9272_2c85,
+  0xa2bf_e8a1, 0xa81a_664b, 0xc24b_8b70, 0xc76c_51a3,
+  0xd192_e819, 0xd699_0624, 0xf40e_3585, 0x106a_a070,
+  0x19a4_c116, 0x1e37_6c08, 0x2748_774c, 0x34b0_bcb5,
+  0x391c_0cb3, 0x4ed8_aa4a, 0x5b9c_ca4f, 0x682e_6ff3,
+  0x748f_82ee, 0x78a5_636f, 0x84c8_7814, 0x8cc7_0208,
+  0x90be_fffa, 0xa450_6ceb, 0xbef9_a3f7, 0xc671_78f2,
+];
+
+/// Hashes the input with SHA-256, as used for the passwords of
+/// basic authentication so that they are not kept in plaintext.
+pub fn sha256(input: &[u8]) -> [u8; 32] {
+  let mut state: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+  ];
+
+  // The input is padded with a one bit, zeros and its length in bits
+  let mut message = input.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  let bits = (input.len() as u64).wrapping_mul(8);
+  for i in (0..8).rev() {
+    message.push((bits >> (i * 8)) as u8);
+  }
+
+  for block in message.chunks(64) {
+    let mut w = [0u32; 64];
+    for i in 0..16 {
+      let word = &block[i * 4..i * 4 + 4];
+      w[i] =
+        (word[0] as u32) << 24 | (word[1] as u32) << 16 | (word[2] as u32) << 8 | word[3] as u32;
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16]
+        .wrapping_add(s0)
+        .wrapping_add(w[i - 7])
+        .wrapping_add(s1);
+    }
+
+    let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+    let (mut e, mut f, mut g, mut h) = (state[4], state[5], state[6], state[7]);
+    for i in 0..64 {
+      let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+      let ch = (e & f) ^ (!e & g);
+      let t1 = h
+        .wrapping_add(s1)
+        .wrapping_add(ch)
+        .wrapping_add(SHA256_K[i])
+        .wrapping_add(w[i]);
+      let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+      let maj = (a & b) ^ (a & c) ^ (b & c);
+      let t2 = s0.wrapping_add(maj);
+      h = g;
+      g = f;
+      f = e;
+      e = d.wrapping_add(t1);
+      d = c;
+      c = b;
+      b = a;
+      a = t1.wrapping_add(t2);
+    }
+    for (word, value) in state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+      *word = word.wrapping_add(*value);
+    }
+  }
+
+  let mut hash = [0u8; 32];
+  for (i, word) in state.iter().enumerate() {
+    hash[i * 4] = (word >> 24) as u8;
+    hash[i * 4 + 1] = (word >> 16) as u8;
+    hash[i * 4 + 2] = (word >> 8) as u8;
+    hash[i * 4 + 3] = *word as u8;
+  }
+  hash
+}
+
+/// Quotes a string for JSON.
+pub fn json_string(s: &str) -> String {
+  let mut quoted = String::with_capacity(s.len() + 2);
+  quoted.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  #[test]
+  fn sha256_vectors() {
+    let long = "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    let vectors = vec![
+      (
+        "".to_string(),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+      ),
+      (
+        "abc".to_string(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+      ),
+      // Padded into a second block
+      (
+        long.to_string(),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+      ),
+      (
+        "a".repeat(1_000_000),
+        "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+      ),
+    ];
+    for (input, expected) in vectors {
+      assert_eq!(
+        expected,
+        hex(&sha256(input.as_bytes())),
+        "hash of {} bytes",
+        input.len()
+      );
+    }
+  }
+
+  #[test]
+  fn decode_base64_padding() {
+    assert_eq!(Some(b"".to_vec()), decode_base64(""));
+    assert_eq!(Some(b"f".to_vec()), decode_base64("Zg=="));
+    assert_eq!(Some(b"fo".to_vec()), decode_base64("Zm8="));
+    assert_eq!(Some(b"foo".to_vec()), decode_base64("Zm9v"));
+    assert_eq!(
+      Some(b"admin:secret".to_vec()),
+      decode_base64("YWRtaW46c2VjcmV0")
+    );
+
+    let invalid = vec!["Zg", "Zg=", "Z===", "Zg==Zm9v", "Zm9v!A==", "Zm 9v", "===="];
+    for input in invalid {
+      assert_eq!(None, decode_base64(input), "{} was decoded", input);
+    }
+  }
+
+  #[test]
+  fn percent_decoding() {
+    assert_eq!(Some(b"/a/b".to_vec()), percent_decode("/a%2Fb"));
+    assert_eq!(Some(b"a\0b".to_vec()), percent_decode("a%00b"));
+    assert_eq!(Some(b"a b".to_vec()), percent_decode("a%20b"));
+    assert_eq!(Some(vec![0xff]), percent_decode("%fF"));
+    for input in &["%", "a%2", "%g0", "%+1", "%2%41"] {
+      assert_eq!(None, percent_decode(input), "{} was decoded", input);
+    }
+
+    assert_eq!("a%20b%2F%25-._~", percent_encode("a b/%-._~"));
+    assert_eq!(
+      Some("a b/%".as_bytes().to_vec()),
+      percent_decode(&percent_encode("a b/%"))
+    );
+  }
 
-  let res = String::from_utf8(buffer_vec)?;
-  Ok(res)
+  #[test]
+  fn constant_time_comparison() {
+    assert!(constant_time_eq(b"secret", b"secret"));
+    assert!(constant_time_eq(b"", b""));
+    assert!(!constant_time_eq(b"secret", b"secreT"));
+    assert!(!constant_time_eq(b"secret", b"secrets"));
+    assert!(!constant_time_eq(b"", b"a"));
+  }
 }
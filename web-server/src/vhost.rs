@@ -0,0 +1,176 @@
+use std::{collections::HashMap, sync::Arc};
+
+use http::{self, content::Contentable};
+use logger::Context;
+use router::Router;
+
+/// Chooses a router from the `Host` header of the request.
+/// Host names are first matched exactly, then against the
+/// wildcard names with the longest suffix first. Requests
+/// which do not match any name go to the default router.
+pub struct VirtualHosts {
+  exact: HashMap<String, Arc<Router>>,
+  wildcards: Vec<(String, Arc<Router>)>,
+  default: Arc<Router>,
+}
+
+impl VirtualHosts {
+  pub fn new(default: Arc<Router>) -> VirtualHosts {
+    VirtualHosts {
+      exact: HashMap::new(),
+      wildcards: Vec::new(),
+      default,
+    }
+  }
+
+  /// Adds a host name for the router. A name starting with `*.`
+  /// matches all subdomains, but not the domain itself.
+  pub fn add_host(&mut self, host: &str, router: Arc<Router>) {
+    let host = host.to_lowercase();
+    if host.starts_with("*.") {
+      self.wildcards.push((host[1..].to_string(), router));
+      self
+        .wildcards
+        .sort_by(|&(ref a, _), &(ref b, _)| b.len().cmp(&a.len()));
+    } else {
+      self.exact.insert(host, router);
+    }
+  }
+
+  /// Finds the router for a value of the `Host` header.
+  pub fn find(&self, host: &str) -> &Router {
+    let host = strip_port(host).trim_right_matches('.').to_lowercase();
+
+    self
+      .exact
+      .get(&host)
+      .or_else(|| {
+        self
+          .wildcards
+          .iter()
+          .find(|&&(ref suffix, _)| host.ends_with(suffix.as_str()) && host.len() > suffix.len())
+          .map(|&(_, ref router)| router)
+      })
+      .unwrap_or(&self.default)
+  }
+
+  /// Gives an error response without a body the body for its status
+  /// from the site of the host, or of the default site without one.
+  pub fn add_error_body(
+    &self,
+    host: Option<&str>,
+    accept: Option<&str>,
+    response: &mut http::Response,
+    context: &Context,
+  ) {
+    let router = match host {
+      Some(host) => self.find(host),
+      None => &self.default,
+    };
+    router.add_error_body(accept, response, context);
+  }
+
+  pub fn handle_request(&self, request: http::Request, context: &Context) -> http::Response {
+    match request.has_header("Host").map(|host| host.to_string()) {
+      Some(host) => self.find(&host).handle_request(request, context),
+      // Every HTTP/1.1 request has to include the `Host` header (RFC 7230, 5.4)
+      None if request.version() == http::HttpVersion::Http11 => {
+        let mut response = http::Response::new(http::StatusCode::BadRequest, "");
+        self
+          .default
+          .add_error_body(request.has_header("Accept"), &mut response, context);
+        response
+      }
+      None => self.default.handle_request(request, context),
+    }
+  }
+}
+
+/// Removes the port from a host, keeping the brackets
+/// around IPv6 addresses.
+fn strip_port(host: &str) -> &str {
+  let port_start = if host.starts_with('[') {
+    host.find(']').map(|pos| pos + 1)
+  } else {
+    host.rfind(':')
+  };
+
+  match port_start {
+    Some(pos) if host[pos..].starts_with(':') => &host[..pos],
+    _ => host,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::convert::TryFrom;
+  use config::{CacheConfig, SiteConfig};
+  use files::FileCache;
+  use router::Routes;
+
+  fn router(header: &str) -> Arc<Router> {
+    let mut site = SiteConfig::new("./html/");
+    site
+      .headers
+      .push(("X-Site".to_string(), header.to_string()));
+    let cache = Arc::new(FileCache::new(CacheConfig::default()));
+    Arc::new(Router::with_routes(&site, Routes::new(), cache))
+  }
+
+  fn site_for(hosts: &VirtualHosts, req_str: &str) -> Option<String> {
+    let req = http::Request::try_from(req_str.to_string()).expect("Unable to parse request");
+    hosts
+      .handle_request(req, &Context::default())
+      .has_header("X-Site")
+      .map(|s| s.to_string())
+  }
+
+  #[test]
+  fn choose_router_from_host() {
+    let mut hosts = VirtualHosts::new(router("default"));
+    hosts.add_host("example.com", router("exact"));
+    hosts.add_host("*.example.com", router("wildcard"));
+    hosts.add_host("*.api.example.com", router("api"));
+
+    let cases = vec![
+      ("example.com", "exact"),
+      ("EXAMPLE.com:8080", "exact"),
+      ("www.example.com", "wildcard"),
+      ("v1.api.example.com", "api"),
+      ("other.com", "default"),
+      ("[::1]:8080", "default"),
+    ];
+    for (host, expected) in cases {
+      let req_str = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host);
+      assert_eq!(
+        Some(expected.to_string()),
+        site_for(&hosts, &req_str),
+        "Wrong site for host {}",
+        host
+      );
+    }
+  }
+
+  #[test]
+  fn missing_host_is_bad_request() {
+    let hosts = VirtualHosts::new(router("default"));
+    let req_str = "GET / HTTP/1.1\r\nAccept: */*\r\n\r\n";
+    let req = http::Request::try_from(req_str.to_string()).expect("Unable to parse request");
+    assert_eq!(
+      http::StatusCode::BadRequest,
+      hosts.handle_request(req, &Context::default()).status_code()
+    );
+
+    let req_str = "GET / HTTP/1.0\r\nAccept: */*\r\n\r\n";
+    assert_eq!(Some("default".to_string()), site_for(&hosts, req_str));
+  }
+
+  #[test]
+  fn strip_port_from_host() {
+    assert_eq!("localhost", strip_port("localhost:8080"));
+    assert_eq!("localhost", strip_port("localhost"));
+    assert_eq!("[::1]", strip_port("[::1]:80"));
+    assert_eq!("[::1]", strip_port("[::1]"));
+  }
+}